@@ -0,0 +1,11 @@
+CREATE TABLE api_keys (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	name TEXT NOT NULL UNIQUE,
+	key_hash BLOB NOT NULL UNIQUE,
+	scopes TEXT NOT NULL,
+	created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	expires DATETIME,
+	last_used DATETIME
+);
+
+ALTER TABLE pastes ADD COLUMN key_id INTEGER REFERENCES api_keys(id);
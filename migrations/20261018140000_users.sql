@@ -0,0 +1,16 @@
+CREATE TABLE users (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+	password TEXT NOT NULL,
+	created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE sessions (
+	token_hash BLOB PRIMARY KEY NOT NULL,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	expires DATETIME NOT NULL
+);
+
+ALTER TABLE pastes ADD COLUMN owner_id INTEGER REFERENCES users(id);
+
+CREATE INDEX pastes_owner_id ON pastes (owner_id);
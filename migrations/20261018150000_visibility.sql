@@ -0,0 +1,3 @@
+ALTER TABLE pastes ADD COLUMN visibility TEXT NOT NULL DEFAULT 'unlisted' CHECK (visibility IN ('public', 'unlisted', 'private'));
+
+CREATE INDEX pastes_visibility ON pastes (visibility);
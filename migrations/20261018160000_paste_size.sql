@@ -0,0 +1,5 @@
+ALTER TABLE pastes ADD COLUMN size INTEGER NOT NULL DEFAULT 0;
+
+UPDATE pastes SET size = length(CAST(content AS BLOB));
+
+CREATE INDEX pastes_ip_posted ON pastes (ip, posted);
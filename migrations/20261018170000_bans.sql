@@ -0,0 +1,11 @@
+CREATE TABLE bans (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	network TEXT NOT NULL,
+	first BLOB NOT NULL,
+	last BLOB NOT NULL,
+	reason TEXT,
+	created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	expires DATETIME
+);
+
+CREATE INDEX bans_range ON bans (first, last);
@@ -0,0 +1,11 @@
+CREATE TABLE reports (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	paste_id INTEGER NOT NULL REFERENCES pastes(id) ON DELETE CASCADE,
+	reason TEXT NOT NULL,
+	ip BLOB NOT NULL,
+	created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX reports_paste_id ON reports (paste_id);
+
+ALTER TABLE pastes ADD COLUMN hidden BOOLEAN NOT NULL DEFAULT 0;
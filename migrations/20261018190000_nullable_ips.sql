@@ -0,0 +1,52 @@
+-- SQLite can't drop NOT NULL from a column, so the tables have to be rebuilt.
+-- Reports are moved out of the way first, so dropping the old pastes table doesn't cascade to them.
+CREATE TABLE reports_old AS SELECT * FROM reports;
+DROP TABLE reports;
+
+CREATE TABLE pastes_new (
+	id INTEGER PRIMARY KEY NOT NULL,
+	name TEXT,
+	ip BLOB,
+	syntax TEXT,
+	posted DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	content TEXT NOT NULL,
+	password TEXT,
+	key_id INTEGER REFERENCES api_keys(id),
+	owner_id INTEGER REFERENCES users(id),
+	visibility TEXT NOT NULL DEFAULT 'unlisted' CHECK (visibility IN ('public', 'unlisted', 'private')),
+	size INTEGER NOT NULL DEFAULT 0,
+	hidden BOOLEAN NOT NULL DEFAULT 0
+);
+
+INSERT INTO pastes_new
+	(id, name, ip, syntax, posted, content, password, key_id, owner_id, visibility, size, hidden)
+SELECT
+	id, name, ip, syntax, posted, content, password, key_id, owner_id, visibility, size, hidden
+FROM
+	pastes;
+
+DROP TABLE pastes;
+ALTER TABLE pastes_new RENAME TO pastes;
+
+CREATE INDEX pastes_owner_id ON pastes (owner_id);
+CREATE INDEX pastes_visibility ON pastes (visibility);
+CREATE INDEX pastes_ip_posted ON pastes (ip, posted);
+
+CREATE TABLE reports (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	paste_id INTEGER NOT NULL REFERENCES pastes(id) ON DELETE CASCADE,
+	reason TEXT NOT NULL,
+	ip BLOB,
+	created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO reports
+	(id, paste_id, reason, ip, created)
+SELECT
+	id, paste_id, reason, ip, created
+FROM
+	reports_old;
+
+DROP TABLE reports_old;
+
+CREATE INDEX reports_paste_id ON reports (paste_id);
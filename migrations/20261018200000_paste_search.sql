@@ -0,0 +1,18 @@
+CREATE VIRTUAL TABLE pastes_fts USING fts5(name, content, content='pastes', content_rowid='id');
+
+INSERT INTO pastes_fts (pastes_fts) VALUES ('rebuild');
+
+CREATE TRIGGER pastes_fts_insert AFTER INSERT ON pastes BEGIN
+	INSERT INTO pastes_fts (rowid, name, content) VALUES (new.id, new.name, new.content);
+END;
+
+CREATE TRIGGER pastes_fts_delete AFTER DELETE ON pastes BEGIN
+	INSERT INTO pastes_fts (pastes_fts, rowid, name, content) VALUES ('delete', old.id, old.name, old.content);
+END;
+
+CREATE TRIGGER pastes_fts_update AFTER UPDATE OF name, content ON pastes BEGIN
+	INSERT INTO pastes_fts (pastes_fts, rowid, name, content) VALUES ('delete', old.id, old.name, old.content);
+	INSERT INTO pastes_fts (rowid, name, content) VALUES (new.id, new.name, new.content);
+END;
+
+CREATE INDEX pastes_browse ON pastes (visibility, id);
@@ -0,0 +1,18 @@
+CREATE TABLE pastes (
+	id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	name TEXT,
+	ip BYTEA,
+	syntax TEXT,
+	posted TIMESTAMPTZ NOT NULL DEFAULT now(),
+	content TEXT NOT NULL,
+	password TEXT,
+	-- API keys and users are kept in each server's SQLite database, so these can't reference them.
+	key_id BIGINT,
+	owner_id BIGINT,
+	visibility TEXT NOT NULL DEFAULT 'unlisted' CHECK (visibility IN ('public', 'unlisted', 'private')),
+	size BIGINT NOT NULL DEFAULT 0,
+	hidden BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX pastes_owner_id ON pastes (owner_id);
+CREATE INDEX pastes_ip_posted ON pastes (ip, posted);
@@ -0,0 +1,66 @@
+-- Accounts, API keys, bans and reports move in alongside pastes, so every server sharing the database sees the same ones.
+CREATE FUNCTION utc_text(TIMESTAMPTZ) RETURNS TEXT
+	LANGUAGE SQL IMMUTABLE
+	AS $$ SELECT to_char($1 AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS') $$;
+
+CREATE TABLE users (
+	id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	username TEXT NOT NULL,
+	password TEXT NOT NULL,
+	created TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX users_username ON users (lower(username));
+
+CREATE TABLE sessions (
+	token_hash BYTEA PRIMARY KEY,
+	user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	expires TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE api_keys (
+	id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	name TEXT NOT NULL UNIQUE,
+	key_hash BYTEA NOT NULL UNIQUE,
+	scopes TEXT NOT NULL,
+	created TIMESTAMPTZ NOT NULL DEFAULT now(),
+	expires TIMESTAMPTZ,
+	last_used TIMESTAMPTZ
+);
+
+-- These used to refer to each server's own SQLite database, so they can't be trusted to mean the same user or key here.
+UPDATE pastes SET owner_id = NULL, key_id = NULL WHERE owner_id IS NOT NULL OR key_id IS NOT NULL;
+
+ALTER TABLE pastes
+	ADD FOREIGN KEY (owner_id) REFERENCES users(id),
+	ADD FOREIGN KEY (key_id) REFERENCES api_keys(id);
+
+CREATE INDEX pastes_key_id ON pastes (key_id);
+
+CREATE TABLE bans (
+	id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	network TEXT NOT NULL,
+	first BYTEA NOT NULL,
+	last BYTEA NOT NULL,
+	reason TEXT,
+	created TIMESTAMPTZ NOT NULL DEFAULT now(),
+	expires TIMESTAMPTZ
+);
+
+CREATE INDEX bans_range ON bans (first, last);
+
+CREATE TABLE reports (
+	id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+	paste_id BIGINT NOT NULL REFERENCES pastes(id) ON DELETE CASCADE,
+	reason TEXT NOT NULL,
+	ip BYTEA,
+	created TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX reports_paste_id ON reports (paste_id);
+
+CREATE INDEX pastes_browse ON pastes (visibility, id);
+
+-- tsvectors can't be bigger than 1 MB, so only the start of each paste is searched.
+CREATE INDEX pastes_search ON pastes
+	USING GIN (to_tsvector('simple', left(coalesce(name, '') || ' ' || content, 262144)));
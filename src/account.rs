@@ -0,0 +1,90 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{error::Error, password, storage::Storage};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// The name of the cookie that holds the session token.
+pub const SESSION_COOKIE: &str = "session";
+
+/// A logged-in user.
+#[derive(Clone, sqlx::FromRow)]
+pub struct User {
+	pub id: i64,
+	pub username: String,
+}
+
+/// Checks that a username is 1-32 characters of letters, digits, `-` or `_`.
+pub fn valid_username(username: &str) -> bool {
+	(1..=32).contains(&username.len())
+		&& username
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Creates a new user, returning whether the username was free.
+pub async fn create_user(
+	storage: &dyn Storage,
+	username: &str,
+	password: String,
+) -> Result<bool, Error> {
+	if !valid_username(username) {
+		return Err(Error::InvalidUsername);
+	}
+	let password = password::hash(password).await?;
+	storage.create_user(username, &password).await
+}
+
+/// Finds a user by name.
+pub async fn find_user(storage: &dyn Storage, username: &str) -> Result<Option<User>, Error> {
+	Ok(storage.find_user(username).await?.map(|(user, _)| user))
+}
+
+/// Checks a username and password, returning the user if they match.
+pub async fn login(
+	storage: &dyn Storage,
+	username: &str,
+	password: String,
+) -> Result<Option<User>, Error> {
+	let (user, hash) = match storage.find_user(username).await? {
+		Some(user) => user,
+		None => return Ok(None),
+	};
+	Ok(if password::verify(hash, password).await? {
+		Some(user)
+	} else {
+		None
+	})
+}
+
+/// Starts a new session for a user, returning the token to put in their cookie.
+pub async fn create_session(
+	storage: &dyn Storage,
+	user: &User,
+	days: u32,
+) -> Result<String, Error> {
+	let mut token = [0_u8; 32];
+	rand::thread_rng().fill_bytes(&mut token);
+	let token = hex::encode(token);
+	let token_hash = Sha256::digest(token.as_bytes()).to_vec();
+	storage.create_session(&token_hash, user.id, days).await?;
+	Ok(token)
+}
+
+/// Finds the user that a session token belongs to, if it's still valid.
+pub async fn session_user(storage: &dyn Storage, token: &str) -> Result<Option<User>, Error> {
+	let token_hash = Sha256::digest(token.as_bytes()).to_vec();
+	storage.session_user(&token_hash).await
+}
+
+/// Ends a session.
+pub async fn delete_session(storage: &dyn Storage, token: &str) -> Result<(), Error> {
+	let token_hash = Sha256::digest(token.as_bytes()).to_vec();
+	storage.delete_session(&token_hash).await
+}
@@ -0,0 +1,240 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{
+	account::{self, User},
+	error::Error,
+	ratelimit::Route,
+	state::State,
+	storage::Storage,
+};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::{fmt, net::IpAddr, str::FromStr};
+
+/// The prefix every API key starts with,
+/// so we can tell them apart from the instance password.
+const KEY_PREFIX: &str = "sp_";
+
+/// Something an API key is allowed to do.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+	/// Uploading pastes, on non-public instances.
+	Upload,
+	/// Deleting pastes uploaded with the same key.
+	DeleteOwn,
+	/// Deleting any paste.
+	DeleteAny,
+	/// Viewing any private paste.
+	ViewPrivate,
+	/// Everything.
+	Admin,
+}
+
+impl Scope {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Upload => "upload",
+			Self::DeleteOwn => "delete-own",
+			Self::DeleteAny => "delete-any",
+			Self::ViewPrivate => "view-private",
+			Self::Admin => "admin",
+		}
+	}
+}
+
+impl FromStr for Scope {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"upload" => Ok(Self::Upload),
+			"delete-own" => Ok(Self::DeleteOwn),
+			"delete-any" => Ok(Self::DeleteAny),
+			"view-private" => Ok(Self::ViewPrivate),
+			"admin" => Ok(Self::Admin),
+			_ => Err(format!(
+				"unknown scope '{}', expected one of: upload, delete-own, delete-any, view-private, admin",
+				s
+			)),
+		}
+	}
+}
+
+impl fmt::Display for Scope {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+/// What a request is allowed to do.
+#[derive(Clone, Default)]
+pub struct Auth {
+	/// The ID of the API key that was used, if any.
+	pub key_id: Option<i64>,
+	/// The logged-in user, if any.
+	pub user: Option<User>,
+	/// The scopes granted to this request.
+	scopes: Vec<Scope>,
+}
+
+impl Auth {
+	/// Authorization for the instance password, which can do anything.
+	pub fn admin() -> Self {
+		Self {
+			key_id: None,
+			user: None,
+			scopes: vec![Scope::Admin],
+		}
+	}
+
+	/// Adds a logged-in user to this authorization.
+	/// Users may upload pastes, and delete their own.
+	pub fn with_user(mut self, user: User) -> Self {
+		self.user = Some(user);
+		self.scopes
+			.extend_from_slice(&[Scope::Upload, Scope::DeleteOwn]);
+		self
+	}
+
+	/// Whether this request is allowed to do the given thing.
+	pub fn can(&self, scope: Scope) -> bool {
+		self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+	}
+
+	/// Whether this request may view a private paste,
+	/// given who uploaded it.
+	pub fn can_view_private(&self, owner_id: Option<i64>, key_id: Option<i64>) -> bool {
+		self.can(Scope::ViewPrivate)
+			|| (owner_id.is_some() && owner_id == self.user.as_ref().map(|user| user.id))
+			|| (key_id.is_some() && key_id == self.key_id)
+	}
+
+	/// Whether this request was made with an API key or the instance password.
+	/// Unlike logged-in users, these are handed out by the admin,
+	/// so they get more leeway with rate limits and quotas.
+	pub fn is_trusted(&self) -> bool {
+		self.key_id.is_some() || self.can(Scope::Admin)
+	}
+
+	/// Whether this request was authorized at all.
+	pub fn is_authorized(&self) -> bool {
+		!self.scopes.is_empty()
+	}
+}
+
+/// Works out what the given secret is allowed to do.
+/// The secret may be either an API key or the instance password,
+/// optionally prefixed with `Bearer`.
+/// Wrong secrets count against the IP's failed authentication limit.
+pub async fn authorize(state: &State, ip: IpAddr, secret: &str) -> Result<Auth, Error> {
+	// Count this as a failed guess up front, as checking the password is expensive,
+	// and concurrent guesses would all get past the limit before any of them were counted.
+	state.rate_limiter.take(Route::FailedAuth, ip).await?;
+	let auth = check_secret(state, secret).await?;
+	if auth.is_authorized() {
+		// It wasn't a failed guess after all.
+		state.rate_limiter.refund(Route::FailedAuth, ip).await;
+	}
+	Ok(auth)
+}
+
+/// Works out what the given secret is allowed to do.
+async fn check_secret(state: &State, secret: &str) -> Result<Auth, Error> {
+	// Browsers send HTTP Basic credentials, as base64-encoded `username:password`.
+	// The username doesn't matter, only the password does.
+	let basic = secret
+		.strip_prefix("Basic ")
+		.and_then(|encoded| base64::decode(encoded).ok())
+		.and_then(|decoded| String::from_utf8(decoded).ok());
+	let secret = match &basic {
+		Some(credentials) => credentials
+			.split_once(':')
+			.map_or(credentials.as_str(), |(_, password)| password),
+		None => secret.strip_prefix("Bearer ").unwrap_or(secret),
+	};
+	if !secret.starts_with(KEY_PREFIX) {
+		return Ok(if state.config.password.verify(secret).await? {
+			Auth::admin()
+		} else {
+			Auth::default()
+		});
+	}
+	let key_hash = Sha256::digest(secret.as_bytes()).to_vec();
+	// Find the key, as long as it hasn't expired, and mark it as used.
+	let key = state.storage.use_key(&key_hash).await?;
+	Ok(match key {
+		Some((id, scopes)) => Auth {
+			key_id: Some(id),
+			user: None,
+			scopes: scopes
+				.split(',')
+				.filter_map(|scope| scope.parse().ok())
+				.collect(),
+		},
+		None => Auth::default(),
+	})
+}
+
+/// Works out what a request is allowed to do,
+/// from its `Authorization` header and session cookie.
+pub async fn resolve(
+	state: &State,
+	ip: IpAddr,
+	authorization: Option<String>,
+	session: Option<String>,
+) -> Result<Auth, Error> {
+	let auth = match authorization {
+		Some(secret) => authorize(state, ip, &secret).await?,
+		None => Auth::default(),
+	};
+	// Session cookies are ignored entirely if accounts are disabled.
+	let user = match session {
+		Some(token) if state.config.accounts.enabled => {
+			account::session_user(&*state.storage, &token).await?
+		}
+		_ => None,
+	};
+	Ok(match user {
+		Some(user) => auth.with_user(user),
+		None => auth,
+	})
+}
+
+/// An API key, as stored in the database.
+#[derive(sqlx::FromRow)]
+pub struct ApiKey {
+	pub name: String,
+	pub scopes: String,
+	pub created: String,
+	pub expires: Option<String>,
+	pub last_used: Option<String>,
+}
+
+/// Creates a new API key, returning the secret.
+/// The secret itself is never stored, so this is the only chance to see it.
+pub async fn create_key(
+	storage: &dyn Storage,
+	name: &str,
+	scopes: &[Scope],
+	expires_in_days: Option<u32>,
+) -> Result<String, Error> {
+	let mut secret = [0_u8; 32];
+	rand::thread_rng().fill_bytes(&mut secret);
+	let secret = format!("{}{}", KEY_PREFIX, hex::encode(secret));
+	let key_hash = Sha256::digest(secret.as_bytes()).to_vec();
+	let scopes = scopes
+		.iter()
+		.map(Scope::as_str)
+		.collect::<Vec<_>>()
+		.join(",");
+	storage
+		.create_key(name, &key_hash, &scopes, expires_in_days)
+		.await?;
+	Ok(secret)
+}
@@ -0,0 +1,68 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{config::BackupsConfig, error::Error, storage::Storage};
+use chrono::Utc;
+use std::path::PathBuf;
+
+/// Backups are named after when they were made, like `sweetpaste-20210818T213625Z.db`.
+const PREFIX: &str = "sweetpaste-";
+const SUFFIX: &str = ".db";
+
+/// Backs up the database while it's still in use, returning where the backup was written.
+pub async fn create(storage: &dyn Storage, config: &BackupsConfig) -> Result<PathBuf, Error> {
+	tokio::fs::create_dir_all(&config.directory).await?;
+	// `VACUUM INTO` won't overwrite anything, so backups made within the same second are numbered.
+	let timestamp = Utc::now().format("%Y%m%dT%H%M%SZ");
+	let mut path = config
+		.directory
+		.join(format!("{}{}{}", PREFIX, timestamp, SUFFIX));
+	let mut number = 1;
+	while tokio::fs::metadata(&path).await.is_ok() {
+		number += 1;
+		path = config
+			.directory
+			.join(format!("{}{}-{}{}", PREFIX, timestamp, number, SUFFIX));
+	}
+	storage.backup(&path).await?;
+	Ok(path)
+}
+
+/// Deletes all but the newest backups, returning how many were deleted.
+pub async fn prune(config: &BackupsConfig) -> Result<usize, Error> {
+	if config.keep == 0 {
+		return Ok(0);
+	}
+	// Only look at files we made, in case the directory is shared.
+	let mut backups = Vec::new();
+	let mut entries = tokio::fs::read_dir(&config.directory).await?;
+	while let Some(entry) = entries.next_entry().await? {
+		let name = entry.file_name();
+		if let Some(name) = name.to_str() {
+			if name.starts_with(PREFIX) && name.ends_with(SUFFIX) {
+				let modified = entry.metadata().await?.modified()?;
+				backups.push((modified, entry.path()));
+			}
+		}
+	}
+	// Oldest first.
+	backups.sort();
+	let excess = backups.len().saturating_sub(config.keep);
+	for (_, path) in &backups[..excess] {
+		tokio::fs::remove_file(path).await?;
+	}
+	Ok(excess)
+}
+
+/// Backs up the database, then deletes old backups.
+/// Returns where the backup was written, and how many old ones were deleted.
+pub async fn run(storage: &dyn Storage, config: &BackupsConfig) -> Result<(PathBuf, usize), Error> {
+	let path = create(storage, config).await?;
+	let pruned = prune(config).await?;
+	Ok((path, pruned))
+}
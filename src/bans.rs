@@ -0,0 +1,86 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{
+	config::Config,
+	error::Error,
+	ip::{self, Network},
+	privacy,
+	ratelimit::Route,
+	state::State,
+	storage::Storage,
+};
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// A ban on an IP address or range, as stored in the database.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct Ban {
+	pub id: i64,
+	pub network: String,
+	pub reason: Option<String>,
+	pub created: String,
+	pub expires: Option<String>,
+}
+
+/// Whether bans are enforced on the given route.
+/// Uploads are always checked, views only if `bans.block-views` is set.
+pub fn applies(state: &State, route: Route) -> bool {
+	match route {
+		Route::Upload => true,
+		Route::Get => state.config.bans.block_views,
+		_ => false,
+	}
+}
+
+/// Finds an active ban covering the given IP address, if there is one.
+pub async fn find(storage: &dyn Storage, ip: IpAddr) -> Result<Option<Ban>, Error> {
+	storage.find_ban(&ip::to_bytes(ip)).await
+}
+
+/// Bans an IP address or CIDR range, returning the new ban.
+pub async fn add(
+	storage: &dyn Storage,
+	network: &str,
+	reason: Option<&str>,
+	expires_in_days: Option<u32>,
+) -> Result<Ban, Error> {
+	let network = network.parse::<Network>()?;
+	// Store the network in its normalized form, along with the range it covers.
+	let (first, last) = ip::range(network.address(), network.prefix(), network.prefix());
+	storage
+		.add_ban(
+			&network.to_string(),
+			(&first, &last),
+			reason,
+			expires_in_days,
+		)
+		.await
+}
+
+/// Bans whoever uploaded the paste with the given ID.
+/// Returns [None] if there's no such paste, or if its uploader's IP isn't known,
+/// as it's been hashed or erased.
+pub async fn add_by_paste(
+	storage: &dyn Storage,
+	config: &Config,
+	paste_id: i64,
+	reason: Option<&str>,
+	expires_in_days: Option<u32>,
+) -> Result<Option<Ban>, Error> {
+	match storage
+		.uploader(paste_id)
+		.await?
+		.and_then(|stored| privacy::network(config, &stored))
+	{
+		Some(uploader) => add(storage, &uploader.to_string(), reason, expires_in_days)
+			.await
+			.map(Some),
+		None => Ok(None),
+	}
+}
@@ -9,6 +9,7 @@
 use crate::config::Config;
 use clru::{CLruCache, CLruCacheConfig, WeightScale};
 use fnv::FnvBuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::sync::Mutex;
 
 pub type HtmlCache = Mutex<CLruCache<i64, String, FnvBuildHasher, StringScale>>;
@@ -22,6 +23,62 @@ impl WeightScale<i64, String> for StringScale {
 	}
 }
 
+/// Counts how often pastes are served from the cache.
+#[derive(Default)]
+pub struct CacheStats {
+	/// How many times a paste was found in the cache.
+	hits: AtomicU64,
+	/// How many times a paste had to be loaded from the database.
+	misses: AtomicU64,
+	/// How many pastes were pushed out of the cache to make room for others.
+	evictions: AtomicU64,
+}
+
+impl CacheStats {
+	/// Records a paste being found in the cache.
+	pub fn hit(&self) {
+		self.hits.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Records a paste not being found in the cache.
+	pub fn miss(&self) {
+		self.misses.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// How many times a paste was found in the cache.
+	pub fn hits(&self) -> u64 {
+		self.hits.load(Ordering::Relaxed)
+	}
+
+	/// How many times a paste had to be loaded from the database.
+	pub fn misses(&self) -> u64 {
+		self.misses.load(Ordering::Relaxed)
+	}
+
+	/// How many pastes were pushed out of the cache to make room for others.
+	pub fn evictions(&self) -> u64 {
+		self.evictions.load(Ordering::Relaxed)
+	}
+}
+
+/// Puts a rendered paste in the cache, counting anything that gets pushed out for it.
+pub fn insert(
+	cache: &mut CLruCache<i64, String, FnvBuildHasher, StringScale>,
+	stats: &CacheStats,
+	id: i64,
+	rendered: String,
+) {
+	let before = cache.len();
+	// A new entry adds one to the length, and replacing an old one doesn't,
+	// so anything else missing was evicted.
+	let added = match cache.put_with_weight(id, rendered) {
+		Ok(None) => 1,
+		Ok(Some(_)) | Err(_) => 0,
+	};
+	let evicted = (before + added).saturating_sub(cache.len());
+	stats.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+}
+
 // Initializes the cache, using the given configuration.
 pub fn create_cache(config: &Config) -> HtmlCache {
 	let config = CLruCacheConfig::new(config.cache_limit)
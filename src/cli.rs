@@ -0,0 +1,582 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{
+	account,
+	auth::{self, Scope},
+	backup, bans,
+	config::{Config, Source},
+	export,
+	id::Id,
+	ip::Network,
+	password, pastes, privacy,
+	state::State,
+	storage,
+};
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::{fs::File, io::BufReader, net::SocketAddr, path::PathBuf};
+
+/// A sweet & simple pastebin, with syntax highlighting and no client-side code.
+#[derive(Parser)]
+#[clap(version, about)]
+pub struct Args {
+	/// The configuration file to use, instead of config.toml.
+	#[clap(short, long, global = true)]
+	pub config: Option<PathBuf>,
+	/// What to do. Runs the server if omitted.
+	#[clap(subcommand)]
+	pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+	/// Runs the server.
+	Serve(ServeArgs),
+	/// Checks the configuration, and prints it out, with every default filled in.
+	CheckConfig,
+	/// Makes a new random key, for use as the `id-key` in config.toml.
+	GenKey,
+	/// Hashes a password, for use as the `password` in config.toml.
+	HashPassword,
+	/// Manages API keys.
+	#[clap(subcommand)]
+	Key(KeyCommand),
+	/// Manages user accounts.
+	#[clap(subcommand)]
+	User(UserCommand),
+	/// Manages IP bans.
+	#[clap(subcommand)]
+	Ban(BanCommand),
+	/// Manages pastes.
+	#[clap(subcommand)]
+	Paste(PasteCommand),
+	/// Writes out every paste as JSON Lines, for backups or moving to another server.
+	Export {
+		/// The file to write to. Writes to stdout if omitted.
+		file: Option<PathBuf>,
+	},
+	/// Reads pastes in from JSON Lines, as written by `export`.
+	/// Pastes keep their IDs, so their URLs still work as long as `id-key` is the same.
+	Import {
+		/// The file to read from. Reads from stdin if omitted.
+		file: Option<PathBuf>,
+	},
+	/// Backs up the database to `backups.directory`, deleting the oldest backups past `backups.keep`.
+	/// This is safe to run while the server is running.
+	Backup,
+}
+
+#[derive(Parser)]
+pub struct ServeArgs {
+	/// The address/port to bind the web server to, instead of `address`.
+	#[clap(short, long)]
+	pub address: Option<SocketAddr>,
+	/// Where the database is, instead of `db-path`.
+	#[clap(short, long)]
+	pub db: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+pub enum KeyCommand {
+	/// Creates a new API key, and prints it.
+	Create {
+		/// A unique name for the key, to tell it apart from others.
+		name: String,
+		/// What the key may do: upload, delete-own, delete-any, view-private, or admin.
+		#[clap(short, long = "scope", required = true)]
+		scopes: Vec<Scope>,
+		/// How many days the key is valid for. Never expires if omitted.
+		#[clap(short, long)]
+		expires_in_days: Option<u32>,
+	},
+	/// Lists every API key.
+	List,
+	/// Revokes an API key.
+	Revoke {
+		/// The name of the key to revoke.
+		name: String,
+	},
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+	/// Creates a new user, prompting for their password.
+	Add {
+		/// The new user's name.
+		username: String,
+	},
+	/// Lists every user.
+	List,
+	/// Deletes a user. Their pastes are kept.
+	Remove {
+		/// The name of the user to delete.
+		username: String,
+	},
+}
+
+#[derive(Subcommand)]
+pub enum BanCommand {
+	/// Bans an IP address or CIDR range, such as `192.0.2.0/24`.
+	Add {
+		/// The IP address or CIDR range to ban.
+		network: String,
+		/// Why they were banned. Shown to the banned client.
+		#[clap(short, long)]
+		reason: Option<String>,
+		/// How many days the ban lasts for. Never expires if omitted.
+		#[clap(short, long)]
+		expires_in_days: Option<u32>,
+	},
+	/// Bans whoever uploaded a paste.
+	Paste {
+		/// The ID of the paste, as in its URL.
+		id: String,
+		/// Why they were banned. Shown to the banned client.
+		#[clap(short, long)]
+		reason: Option<String>,
+		/// How many days the ban lasts for. Never expires if omitted.
+		#[clap(short, long)]
+		expires_in_days: Option<u32>,
+	},
+	/// Lists every active ban.
+	List,
+	/// Lifts a ban.
+	Lift {
+		/// The ID of the ban, as shown by `sweetpaste ban list`.
+		id: i64,
+	},
+}
+
+#[derive(Subcommand)]
+pub enum PasteCommand {
+	/// Lists pastes, newest first.
+	List {
+		/// Only pastes posted on or after this date, like `2021-08-18`.
+		#[clap(long, value_parser = parse_date)]
+		since: Option<String>,
+		/// Only pastes posted before this date, like `2021-08-18`.
+		#[clap(long, value_parser = parse_date)]
+		until: Option<String>,
+		/// Only pastes in this language, like `Rust`.
+		#[clap(short, long)]
+		language: Option<String>,
+		/// Only pastes uploaded from this IP address or CIDR range.
+		#[clap(short, long)]
+		ip: Option<Network>,
+		/// The most pastes to list.
+		#[clap(short = 'n', long, default_value = "100")]
+		limit: u32,
+	},
+	/// Shows a paste's details, and prints its content.
+	Show {
+		/// The ID of the paste, as in its URL.
+		id: String,
+	},
+	/// Deletes a paste.
+	Delete {
+		/// The ID of the paste, as in its URL.
+		id: String,
+	},
+	/// Turns a paste ID, as in its URL, into the number it's stored as.
+	DecodeId {
+		/// The ID of the paste, as in its URL.
+		id: String,
+	},
+	/// Turns the number a paste is stored as into its ID, as in its URL.
+	EncodeId {
+		/// The number the paste is stored as.
+		id: i64,
+	},
+	/// Deletes every paste older than the given number of days.
+	Purge {
+		/// How many days old a paste has to be to be deleted.
+		#[clap(long)]
+		older_than: u32,
+	},
+}
+
+/// Settings that `check-config` won't print.
+/// The database URL is included, as it may well have a password in it.
+const SECRETS: &[&str] = &["password", "id-key", "database-url"];
+
+/// Checks that a date looks like `2021-08-18`.
+fn parse_date(date: &str) -> Result<String, String> {
+	match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+		Ok(_) => Ok(date.to_string()),
+		Err(_) => Err(format!("'{}' isn't a date like 2021-08-18", date)),
+	}
+}
+
+/// Prompts for a new password, twice to make sure it was typed right.
+fn prompt_new_password() -> Result<String> {
+	let password = rpassword::prompt_password("Password: ").wrap_err("failed to read password")?;
+	let confirm =
+		rpassword::prompt_password("Confirm password: ").wrap_err("failed to read password")?;
+	if password != confirm {
+		return Err(eyre!("passwords don't match"));
+	}
+	Ok(password)
+}
+
+/// Loads the configuration, makes sure all of it works, and prints it out.
+pub async fn check_config(source: &Source) -> Result<()> {
+	let config = Config::load(source)
+		.await
+		.wrap_err("failed to load config")?;
+	State::check(&config)?;
+	let mut config = toml::Value::try_from(&config).wrap_err("failed to serialize config")?;
+	// Don't print secrets, as the output may well end up in a bug report.
+	if let Some(table) = config.as_table_mut() {
+		for secret in SECRETS {
+			if let Some(value) = table.get_mut(*secret) {
+				*value = toml::Value::String("<redacted>".to_string());
+			}
+		}
+	}
+	print!("{}", config);
+	eprintln!("The configuration is valid.");
+	Ok(())
+}
+
+/// Prints a new random ID key.
+pub fn gen_key() -> Result<()> {
+	println!("id-key = \"{}\"", Config::generate_id_key());
+	Ok(())
+}
+
+/// Prompts for a password, and prints its Argon2 hash.
+pub async fn hash_password() -> Result<()> {
+	let password = prompt_new_password()?;
+	let hash = password::hash(password)
+		.await
+		.wrap_err("failed to hash password")?;
+	println!("password = \"{}\"", hash);
+	Ok(())
+}
+
+/// Runs an API key management command.
+pub async fn key(source: &Source, command: KeyCommand) -> Result<()> {
+	let config = Config::load(source)
+		.await
+		.wrap_err("failed to load config")?;
+	let storage = storage::open(&config)
+		.await
+		.wrap_err("failed to open database")?;
+	match command {
+		KeyCommand::Create {
+			name,
+			scopes,
+			expires_in_days,
+		} => {
+			let secret = auth::create_key(&*storage, &name, &scopes, expires_in_days)
+				.await
+				.wrap_err("failed to create key")?;
+			println!("{}", secret);
+			eprintln!("This key won't be shown again, so keep it somewhere safe!");
+		}
+		KeyCommand::List => {
+			for key in storage.keys().await.wrap_err("failed to list keys")? {
+				println!(
+					"{}\t{}\tcreated {}\texpires {}\tlast used {}",
+					key.name,
+					key.scopes,
+					key.created,
+					key.expires.as_deref().unwrap_or("never"),
+					key.last_used.as_deref().unwrap_or("never")
+				);
+			}
+		}
+		KeyCommand::Revoke { name } => {
+			if !storage
+				.revoke_key(&name)
+				.await
+				.wrap_err("failed to revoke key")?
+			{
+				return Err(eyre!("no key named '{}'", name));
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Runs a user management command.
+pub async fn user(source: &Source, command: UserCommand) -> Result<()> {
+	let config = Config::load(source)
+		.await
+		.wrap_err("failed to load config")?;
+	let storage = storage::open(&config)
+		.await
+		.wrap_err("failed to open database")?;
+	match command {
+		UserCommand::Add { username } => {
+			let password = prompt_new_password()?;
+			if !account::create_user(&*storage, &username, password)
+				.await
+				.wrap_err("failed to create user")?
+			{
+				return Err(eyre!("there's already a user named '{}'", username));
+			}
+		}
+		UserCommand::List => {
+			for username in storage.users().await.wrap_err("failed to list users")? {
+				println!("{}", username);
+			}
+		}
+		UserCommand::Remove { username } => {
+			if !storage
+				.delete_user(&username)
+				.await
+				.wrap_err("failed to delete user")?
+			{
+				return Err(eyre!("no user named '{}'", username));
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Prints a ban, in the same format as `sweetpaste ban list`.
+fn print_ban(ban: &bans::Ban) {
+	println!(
+		"{}\t{}\tcreated {}\texpires {}\t{}",
+		ban.id,
+		ban.network,
+		ban.created,
+		ban.expires.as_deref().unwrap_or("never"),
+		ban.reason.as_deref().unwrap_or("")
+	);
+}
+
+/// Runs a ban management command.
+pub async fn ban(source: &Source, command: BanCommand) -> Result<()> {
+	let config = Config::load(source)
+		.await
+		.wrap_err("failed to load config")?;
+	let storage = storage::open(&config)
+		.await
+		.wrap_err("failed to open database")?;
+	match command {
+		BanCommand::Add {
+			network,
+			reason,
+			expires_in_days,
+		} => {
+			let ban = bans::add(&*storage, &network, reason.as_deref(), expires_in_days)
+				.await
+				.wrap_err("failed to add ban")?;
+			print_ban(&ban);
+		}
+		BanCommand::Paste {
+			id,
+			reason,
+			expires_in_days,
+		} => {
+			// Decoding paste IDs needs the rest of the state, for the ID key.
+			let state = State::new(source.clone())
+				.await
+				.wrap_err("failed to initialize sweetpaste")?;
+			let id = Id::decode(&state, &id)
+				.await
+				.wrap_err("failed to decode paste ID")?;
+			match bans::add_by_paste(
+				&*storage,
+				&config,
+				i64::from(id),
+				reason.as_deref(),
+				expires_in_days,
+			)
+			.await
+			.wrap_err("failed to add ban")?
+			{
+				Some(ban) => print_ban(&ban),
+				None => return Err(eyre!("no such paste, or its uploader's IP isn't known")),
+			}
+		}
+		BanCommand::List => {
+			for ban in storage.bans().await.wrap_err("failed to list bans")? {
+				print_ban(&ban);
+			}
+		}
+		BanCommand::Lift { id } => {
+			if !storage.lift_ban(id).await.wrap_err("failed to lift ban")? {
+				return Err(eyre!("no ban with ID {}", id));
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Prints a paste's details on one line.
+async fn print_paste(state: &State, paste: &pastes::Paste) {
+	let mut visibility = paste.visibility.clone();
+	if paste.hidden {
+		visibility.push_str(", hidden");
+	}
+	if paste.locked {
+		visibility.push_str(", locked");
+	}
+	println!(
+		"{}\t{}\t{}\t{} bytes\t{}\tip {}\towner {}\t{}",
+		Id::from(paste.id).encode(state).await,
+		paste.posted,
+		paste.syntax.as_deref().unwrap_or("Plain Text"),
+		paste.size,
+		visibility,
+		paste
+			.ip
+			.as_deref()
+			.map(|ip| privacy::display(&state.config, ip))
+			.unwrap_or_else(|| "unknown".to_string()),
+		paste.owner.as_deref().unwrap_or("none"),
+		paste.name.as_deref().unwrap_or("")
+	);
+}
+
+/// Runs a paste management command.
+pub async fn paste(source: &Source, command: PasteCommand) -> Result<()> {
+	// Paste IDs are encrypted with the ID key, which needs the rest of the state.
+	let state = State::new(source.clone())
+		.await
+		.wrap_err("failed to initialize sweetpaste")?;
+	match command {
+		PasteCommand::List {
+			since,
+			until,
+			language,
+			ip,
+			limit,
+		} => {
+			let filter = pastes::Filter {
+				since,
+				until,
+				language,
+				network: ip,
+				limit,
+			};
+			for paste in pastes::list(&*state.storage, &state.config, &filter)
+				.await
+				.wrap_err("failed to list pastes")?
+			{
+				print_paste(&state, &paste).await;
+			}
+		}
+		PasteCommand::Show { id } => {
+			let id = Id::decode(&state, &id)
+				.await
+				.wrap_err("failed to decode paste ID")?;
+			let (paste, content) = pastes::find(&*state.storage, i64::from(id))
+				.await
+				.wrap_err("failed to find paste")?
+				.ok_or_else(|| eyre!("no paste with that ID"))?;
+			// The details go to stderr, so the content can be piped somewhere on its own.
+			eprintln!(
+				"{}\t{} bytes\tposted {}\tip {}\towner {}",
+				paste.syntax.as_deref().unwrap_or("Plain Text"),
+				paste.size,
+				paste.posted,
+				paste
+					.ip
+					.as_deref()
+					.map(|ip| privacy::display(&state.config, ip))
+					.unwrap_or_else(|| "unknown".to_string()),
+				paste.owner.as_deref().unwrap_or("none"),
+			);
+			print!("{}", content);
+		}
+		PasteCommand::Delete { id } => {
+			let decoded = Id::decode(&state, &id)
+				.await
+				.wrap_err("failed to decode paste ID")?;
+			if !pastes::delete(&*state.storage, i64::from(decoded))
+				.await
+				.wrap_err("failed to delete paste")?
+			{
+				return Err(eyre!("no paste with ID '{}'", id));
+			}
+		}
+		PasteCommand::DecodeId { id } => {
+			let id = Id::decode(&state, &id)
+				.await
+				.wrap_err("failed to decode paste ID")?;
+			println!("{}", i64::from(id));
+		}
+		PasteCommand::EncodeId { id } => {
+			println!("{}", Id::from(id).encode(&state).await);
+		}
+		PasteCommand::Purge { older_than } => {
+			let purged = pastes::purge(&*state.storage, older_than)
+				.await
+				.wrap_err("failed to purge pastes")?;
+			eprintln!("Deleted {} pastes.", purged);
+		}
+	}
+	Ok(())
+}
+
+/// Writes out every paste, to a file or stdout.
+pub async fn export(source: &Source, file: Option<PathBuf>) -> Result<()> {
+	let config = Config::load(source)
+		.await
+		.wrap_err("failed to load config")?;
+	let storage = storage::open(&config)
+		.await
+		.wrap_err("failed to open database")?;
+	let exported = match file {
+		Some(path) => {
+			let file = File::create(&path)
+				.wrap_err_with(|| format!("failed to create {}", path.display()))?;
+			export::export(&*storage, file).await?
+		}
+		None => export::export(&*storage, std::io::stdout().lock()).await?,
+	};
+	eprintln!("Exported {} pastes.", exported);
+	Ok(())
+}
+
+/// Reads pastes in, from a file or stdin.
+pub async fn import(source: &Source, file: Option<PathBuf>) -> Result<()> {
+	let config = Config::load(source)
+		.await
+		.wrap_err("failed to load config")?;
+	let storage = storage::open(&config)
+		.await
+		.wrap_err("failed to open database")?;
+	let (imported, skipped) = match file {
+		Some(path) => {
+			let file =
+				File::open(&path).wrap_err_with(|| format!("failed to open {}", path.display()))?;
+			export::import(&*storage, BufReader::new(file)).await?
+		}
+		None => export::import(&*storage, std::io::stdin().lock()).await?,
+	};
+	eprintln!(
+		"Imported {} pastes, skipped {} with IDs that were already taken.",
+		imported, skipped
+	);
+	Ok(())
+}
+
+/// Backs up the database, and prints where the backup was written.
+pub async fn backup(source: &Source) -> Result<()> {
+	let config = Config::load(source)
+		.await
+		.wrap_err("failed to load config")?;
+	let storage = storage::open(&config)
+		.await
+		.wrap_err("failed to open database")?;
+	let (path, pruned) = backup::run(&*storage, &config.backups)
+		.await
+		.wrap_err("failed to back up database")?;
+	println!("{}", path.display());
+	if pruned > 0 {
+		eprintln!("Deleted {} old backups.", pruned);
+	}
+	Ok(())
+}
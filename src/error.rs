@@ -6,6 +6,13 @@
 	file, You can obtain one at http://mozilla.org/MPL/2.0/.
 */
 
+use std::time::Duration;
+use warp::{
+	http::{header, StatusCode},
+	reply::Response,
+	Reply,
+};
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
 	#[error("warp error: {0}")]
@@ -18,12 +25,120 @@ pub enum Error {
 	Mnemonic(#[from] mnemonic::Error),
 	#[error("invalid id")]
 	InvalidId,
+	#[error("invalid query string")]
+	InvalidQuery,
 	#[error("form is missing '{0}' entry")]
 	IncompleteForm(&'static str),
+	#[error(
+		"only uploads with an account or API key can be private, as nobody else could see them"
+	)]
+	PrivateAnonymous,
 	#[error("didn't upload any paste")]
 	EmptyForm,
 	#[error("failed to render: {0}")]
 	Render(#[from] handlebars::RenderError),
+	#[error("password hash error: {0}")]
+	PasswordHash(#[from] argon2::password_hash::Error),
+	#[error("background task failed: {0}")]
+	Join(#[from] tokio::task::JoinError),
+	#[error("too many requests, try again in {} seconds", .0.as_secs_f64().ceil())]
+	RateLimited(Duration),
+	#[error("upload quota exceeded, try again later")]
+	QuotaExceeded,
+	#[error("usernames must be 1-32 letters, numbers, '-' or '_'")]
+	InvalidUsername,
+	#[error("you are banned{}", .0.as_ref().map(|reason| format!(": {}", reason)).unwrap_or_default())]
+	Banned(Option<String>),
+	#[error("you aren't allowed to do that")]
+	Forbidden,
+	#[error("you need to log in to do that")]
+	Unauthorized,
+	#[error("invalid IP address or CIDR range '{0}'")]
+	InvalidNetwork(String),
+	#[error("paste rejected, as {0}")]
+	Rejected(String),
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("{0}")]
+	Unsupported(&'static str),
+}
+
+impl Error {
+	/// The HTTP status code to respond with for this error.
+	pub fn status(&self) -> StatusCode {
+		match self {
+			Self::RateLimited(_) | Self::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+			Self::InvalidUsername
+			| Self::InvalidNetwork(_)
+			| Self::IncompleteForm(_)
+			| Self::InvalidQuery
+			| Self::PrivateAnonymous => StatusCode::BAD_REQUEST,
+			// Anything that doesn't decode to a paste ID can't be a paste.
+			Self::Mnemonic(_) | Self::InvalidId => StatusCode::NOT_FOUND,
+			Self::Banned(_) | Self::Forbidden => StatusCode::FORBIDDEN,
+			Self::Unauthorized => StatusCode::UNAUTHORIZED,
+			Self::Rejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+			Self::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+			_ => StatusCode::INTERNAL_SERVER_ERROR,
+		}
+	}
+
+	/// A short name for this kind of error, for metrics.
+	pub fn kind(&self) -> &'static str {
+		match self {
+			Self::Warp(_) => "warp",
+			Self::InvalidUtf8(_) => "invalid_utf8",
+			Self::Db(_) => "db",
+			Self::Mnemonic(_) => "mnemonic",
+			Self::InvalidId => "invalid_id",
+			Self::InvalidQuery => "invalid_query",
+			Self::IncompleteForm(_) => "incomplete_form",
+			Self::PrivateAnonymous => "private_anonymous",
+			Self::EmptyForm => "empty_form",
+			Self::Render(_) => "render",
+			Self::PasswordHash(_) => "password_hash",
+			Self::Join(_) => "join",
+			Self::RateLimited(_) => "rate_limited",
+			Self::QuotaExceeded => "quota_exceeded",
+			Self::InvalidUsername => "invalid_username",
+			Self::Banned(_) => "banned",
+			Self::Forbidden => "forbidden",
+			Self::Unauthorized => "unauthorized",
+			Self::InvalidNetwork(_) => "invalid_network",
+			Self::Rejected(_) => "rejected",
+			Self::Io(_) => "io",
+			Self::Unsupported(_) => "unsupported",
+		}
+	}
+
+	/// The response the client gets for this error.
+	pub fn response(&self) -> Response {
+		let status = self.status();
+		// Whatever went wrong is hidden from the client, so make sure it's logged.
+		if status.is_server_error() {
+			tracing::error!(error = %self, details = ?self, "internal error");
+		}
+		let mut reply =
+			warp::reply::with_status(warp::reply::html(self.to_string()), status).into_response();
+		match self {
+			// Let rate limited clients know when they can try again.
+			Self::RateLimited(retry_after) => {
+				reply.headers_mut().insert(
+					header::RETRY_AFTER,
+					header::HeaderValue::from(retry_after.as_secs_f64().ceil() as u64),
+				);
+			}
+			// Have browsers ask for the password.
+			Self::Unauthorized => {
+				reply.headers_mut().insert(
+					header::WWW_AUTHENTICATE,
+					header::HeaderValue::from_static("Basic realm=\"sweetpaste\""),
+				);
+			}
+			_ => {}
+		}
+		reply
+	}
 }
 
 impl warp::reject::Reject for Error {}
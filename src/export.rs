@@ -0,0 +1,102 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::storage::{Dump, Storage};
+use color_eyre::eyre::{Result, WrapErr};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+/// A paste, as it's written out in an export, one per line.
+/// IDs are kept as they are, so paste URLs still work after importing with the same `id-key`.
+#[derive(Serialize, Deserialize)]
+pub struct Exported {
+	pub id: i64,
+	pub name: Option<String>,
+	pub syntax: Option<String>,
+	pub posted: String,
+	pub content: String,
+	/// The Argon2 hash of the paste's password.
+	pub password: Option<String>,
+	pub visibility: String,
+	#[serde(default)]
+	pub hidden: bool,
+	/// The uploader's IP, in hex, in whatever form it was stored.
+	pub ip: Option<String>,
+	/// The name of the user who owns the paste.
+	/// Users aren't exported, so this is only kept if there's a user by the same name when importing.
+	pub owner: Option<String>,
+	/// The name of the API key the paste was uploaded with.
+	/// Likewise, this is only kept if there's a key by the same name when importing.
+	pub key: Option<String>,
+}
+
+/// Writes out every paste as JSON Lines, returning how many were written.
+pub async fn export(storage: &dyn Storage, out: impl Write) -> Result<u64> {
+	let mut out = std::io::BufWriter::new(out);
+	let mut pastes = storage.export();
+	let mut exported = 0;
+	while let Some(paste) = pastes.try_next().await.wrap_err("failed to read pastes")? {
+		let paste = Exported {
+			id: paste.id,
+			name: paste.name,
+			syntax: paste.syntax,
+			posted: paste.posted,
+			content: paste.content,
+			password: paste.password,
+			visibility: paste.visibility,
+			hidden: paste.hidden,
+			ip: paste.ip.map(hex::encode),
+			owner: paste.owner,
+			key: paste.key,
+		};
+		serde_json::to_writer(&mut out, &paste).wrap_err("failed to write paste")?;
+		out.write_all(b"\n").wrap_err("failed to write paste")?;
+		exported += 1;
+	}
+	out.flush().wrap_err("failed to write pastes")?;
+	Ok(exported)
+}
+
+/// Reads pastes from JSON Lines, as written by [export], and imports them all in one transaction.
+/// Pastes whose IDs are already taken are skipped.
+/// Returns how many pastes were imported, and how many were skipped.
+pub async fn import(storage: &dyn Storage, input: impl BufRead) -> Result<(u64, u64)> {
+	let mut pastes = Vec::new();
+	for (index, line) in input.lines().enumerate() {
+		let line = line.wrap_err("failed to read pastes")?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		let line_number = index + 1;
+		let paste = serde_json::from_str::<Exported>(&line)
+			.wrap_err_with(|| format!("invalid paste on line {}", line_number))?;
+		let ip = paste
+			.ip
+			.map(hex::decode)
+			.transpose()
+			.wrap_err_with(|| format!("invalid IP on line {}", line_number))?;
+		pastes.push(Dump {
+			id: paste.id,
+			name: paste.name,
+			syntax: paste.syntax,
+			posted: paste.posted,
+			content: paste.content,
+			password: paste.password,
+			visibility: paste.visibility,
+			hidden: paste.hidden,
+			ip,
+			owner: paste.owner,
+			key: paste.key,
+		});
+	}
+	storage
+		.import(pastes)
+		.await
+		.wrap_err("failed to import pastes")
+}
@@ -6,57 +6,220 @@
 	file, You can obtain one at http://mozilla.org/MPL/2.0/.
 */
 
-use crate::state::State;
+use crate::{
+	account,
+	auth::{self, Auth},
+	bans,
+	error::Error,
+	proxy,
+	ratelimit::Route,
+	state::{SharedState, State},
+};
+use serde::de::DeserializeOwned;
 use std::{
 	net::{IpAddr, SocketAddr},
 	sync::Arc,
+	time::Instant,
 };
-use warp::Filter;
+use warp::{http::HeaderMap, reply::Response, Filter, Reply};
 
-/// A warp filter that copies an [`Arc`].
-pub fn with_obj<T: Send + Sync + Clone>(
-	obj: T,
-) -> impl Filter<Extract = (T,), Error = std::convert::Infallible> + Clone {
-	warp::any().map(move || obj.clone())
+/// Wraps a route, recording how many requests it answers, and how long it takes, in the metrics.
+/// Errors from the route itself are answered here, and count too,
+/// but requests it doesn't match at all are left for the other routes.
+pub fn metered<F, R>(
+	state: Arc<SharedState>,
+	route: &'static str,
+	filter: F,
+) -> impl Filter<Extract = (Response,), Error = warp::Rejection> + Clone
+where
+	F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+	R: Reply,
+{
+	let errors = state.clone();
+	warp::any()
+		.map(Instant::now)
+		.and(
+			filter
+				.map(|reply: R| reply.into_response())
+				// Only our own errors mean the request was meant for this route,
+				// so they're answered right away, rather than letting the next route try.
+				// Anything else means it didn't match, and another route might.
+				.or_else(move |rejection: warp::Rejection| {
+					futures::future::ready(match rejection.find::<Error>() {
+						Some(err) => {
+							errors.load().metrics.rejection(err.kind());
+							Ok((err.response(),))
+						}
+						None => Err(rejection),
+					})
+				}),
+		)
+		.map(move |started: Instant, response: Response| {
+			tracing::Span::current().record("route", route);
+			state
+				.load()
+				.metrics
+				.request(route, response.status(), started.elapsed());
+			response
+		})
 }
 
-/// A warp filter which extracts an IP address,
-/// using either the `X-Real-IP` header or the `X-Forwarded-For` header,
-/// or just the plain IP if the origin IP isn't trusted.
+/// A warp filter which gets the current [`State`], which changes whenever the configuration is reloaded.
+pub fn with_state(
+	state: Arc<SharedState>,
+) -> impl Filter<Extract = (Arc<State>,), Error = std::convert::Infallible> + Clone {
+	warp::any().map(move || state.load())
+}
+
+/// A warp filter which extracts the client's IP address.
+/// If the request came from a trusted proxy, the client's IP is taken from
+/// the proxy headers in `proxy-headers`, otherwise it's just the plain IP.
 pub fn with_ip(
-	state: Arc<State>,
+	state: Arc<SharedState>,
 ) -> impl Filter<Extract = (IpAddr,), Error = warp::Rejection> + Clone {
 	warp::filters::addr::remote()
 		// Get the originating IP address.
 		.and_then(|addr: Option<SocketAddr>| async move {
 			addr.map(|socket| socket.ip()).ok_or_else(warp::reject)
 		})
-		// Get either the `CF-Connecting-IP`, `X-Real-IP`, or the `X-Forwarded-For` header.
-		// We map it to an Option so we don't reject if the header isn't present.
-		.and(
-			warp::header::<IpAddr>("cf-connecting-ip")
-				.map(Option::<IpAddr>::Some)
-				.or(warp::header::<IpAddr>("x-real-ip").map(Option::<IpAddr>::Some))
-				.unify()
-				.or(warp::header::<IpAddr>("x-forwarded-for").map(Option::<IpAddr>::Some))
-				.unify()
-				.or_else(|_| async {
-					Result::<_, std::convert::Infallible>::Ok((Option::<IpAddr>::None,))
-				}),
+		// Get every header, as proxies may send more than one of the same header.
+		.and(warp::header::headers_cloned())
+		// Get the current config, we need the `trusted_ips` and `proxy_headers` fields.
+		.and(with_state(state))
+		.map(|origin_ip: IpAddr, headers: HeaderMap, state: Arc<State>| {
+			proxy::client_ip(&state.config, origin_ip, &headers)
+		})
+}
+
+/// A warp filter which extracts an IP address like [with_ip],
+/// rejecting it if it's banned from the given route.
+pub fn with_unbanned_ip(
+	state: Arc<SharedState>,
+	route: Route,
+) -> impl Filter<Extract = (IpAddr,), Error = warp::Rejection> + Clone {
+	with_ip(state.clone()).and(with_state(state)).and_then(
+		move |ip: IpAddr, state: Arc<State>| async move {
+			if bans::applies(&state, route) {
+				if let Some(ban) = bans::find(&*state.storage, ip)
+					.await
+					.map_err(warp::reject::custom)?
+				{
+					return Err(warp::reject::custom(Error::Banned(ban.reason)));
+				}
+			}
+			Ok(ip)
+		},
+	)
+}
+
+/// A warp filter which works out what a request is allowed to do,
+/// using the API key or password in the `Authorization` header,
+/// and the session cookie of a logged-in user.
+pub fn with_auth(
+	state: Arc<SharedState>,
+) -> impl Filter<Extract = (Auth,), Error = warp::Rejection> + Clone {
+	with_ip(state.clone())
+		.and(warp::header::optional::<String>("authorization"))
+		.and(warp::cookie::optional::<String>(account::SESSION_COOKIE))
+		.and(with_state(state))
+		.and_then(
+			|ip: IpAddr,
+			 authorization: Option<String>,
+			 session: Option<String>,
+			 state: Arc<State>| async move {
+				auth::resolve(&state, ip, authorization, session)
+					.await
+					.map_err(warp::reject::custom)
+			},
+		)
+}
+
+/// A warp filter which extracts the IP address and authorization of a request,
+/// like [with_unbanned_ip] and [with_auth], rejecting it if it's over the route's rate limit.
+pub fn rate_limited(
+	state: Arc<SharedState>,
+	route: Route,
+) -> impl Filter<Extract = (IpAddr, Auth), Error = warp::Rejection> + Clone {
+	with_unbanned_ip(state.clone(), route)
+		.and(with_auth(state.clone()))
+		.and(with_state(state))
+		.and_then(
+			move |ip: IpAddr, auth: Auth, state: Arc<State>| async move {
+				// Requests made with an API key or the password may skip the limits.
+				// Logged-in users don't, as anyone may be able to register.
+				if !(auth.is_trusted() && state.rate_limiter.exempts_authorized(route)) {
+					state
+						.rate_limiter
+						.take(route, ip)
+						.await
+						.map_err(warp::reject::custom)?;
+				}
+				Ok::<_, warp::Rejection>((ip, auth))
+			},
 		)
-		// Copy the config, we need the `trusted_ips` field.
-		.and(with_obj(state))
-		// Alright, this is where we do our stuff.
+		.untuple_one()
+}
+
+/// A warp filter which rejects requests that another site's page made a browser send,
+/// so that they can't act with the credentials the browser sends along.
+/// Browsers say where a request came from with `Sec-Fetch-Site`, or `Origin` if they're older.
+/// Requests with neither didn't come from a browser, so they're let through.
+pub fn same_origin(
+	state: Arc<SharedState>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+	warp::header::optional::<String>("sec-fetch-site")
+		.and(warp::header::optional::<String>("origin"))
+		.and(with_state(state))
 		.and_then(
-			|origin_ip: IpAddr, header_ip: Option<IpAddr>, state: Arc<State>| async move {
-				match header_ip {
-					// If the origin is trusted and the IP header is set, use that.
-					Some(ip) if state.config.trusted_ips.contains(&origin_ip) => {
-						Result::<IpAddr, std::convert::Infallible>::Ok(ip)
+			|fetch_site: Option<String>, origin: Option<String>, state: Arc<State>| async move {
+				let allowed = match (fetch_site, origin) {
+					// `none` means the user did it themselves, like with a bookmark.
+					(Some(fetch_site), _) => fetch_site == "same-origin" || fetch_site == "none",
+					(None, Some(origin)) => {
+						origin.eq_ignore_ascii_case(site_origin(&state.config.site_url))
 					}
-					// Otherwise, just use the origin IP.
-					_ => Result::<IpAddr, std::convert::Infallible>::Ok(origin_ip),
+					(None, None) => true,
+				};
+				if allowed {
+					Ok(())
+				} else {
+					Err(warp::reject::custom(Error::Forbidden))
 				}
 			},
 		)
+		.untuple_one()
+}
+
+/// The origin of a URL, which is everything before the path, like `https://paste.example.com`.
+fn site_origin(url: &str) -> &str {
+	let host = url.find("://").map_or(0, |scheme| scheme + 3);
+	match url[host..].find('/') {
+		Some(path) => &url[..host + path],
+		None => url,
+	}
+}
+
+/// A warp filter which parses the query string like [warp::query],
+/// but answers with [Error::InvalidQuery] if it's invalid, rather than leaving it for the other routes.
+pub fn query<T>() -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone
+where
+	T: DeserializeOwned + Send + 'static,
+{
+	warp::query::<T>().or_else(|_| async { Err(warp::reject::custom(Error::InvalidQuery)) })
+}
+
+/// A warp filter which only passes if user accounts are enabled.
+pub fn accounts_enabled(
+	state: Arc<SharedState>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+	warp::any()
+		.and(with_state(state))
+		.and_then(|state: Arc<State>| async move {
+			if state.config.accounts.enabled {
+				Ok(())
+			} else {
+				Err(warp::reject::not_found())
+			}
+		})
+		.untuple_one()
 }
@@ -30,6 +30,8 @@ impl Id {
 		chacha.apply_keystream(&mut id[8 - offset..]);
 		// Seek the cipher back to 0.
 		chacha.seek(0);
+		// Note which paste this request is for, in the logs.
+		tracing::Span::current().record("paste", i64::from_be_bytes(id));
 		// Return the decoded id
 		Ok(Self(id))
 	}
@@ -0,0 +1,267 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::{
+	convert::TryFrom,
+	fmt,
+	net::{IpAddr, Ipv4Addr, Ipv6Addr},
+	str::FromStr,
+};
+
+/// Converts an IP address to bytes, as stored in the database.
+/// IPv4-mapped IPv6 addresses are stored as IPv4.
+pub fn to_bytes(ip: IpAddr) -> Vec<u8> {
+	match ip.to_canonical() {
+		IpAddr::V4(ipv4) => ipv4.octets().to_vec(),
+		IpAddr::V6(ipv6) => ipv6.octets().to_vec(),
+	}
+}
+
+/// The netmask for an IPv4 prefix length.
+fn ipv4_mask(prefix: u8) -> u32 {
+	u32::MAX
+		.checked_shl(32 - u32::from(prefix.min(32)))
+		.unwrap_or(0)
+}
+
+/// The netmask for an IPv6 prefix length.
+fn ipv6_mask(prefix: u8) -> u128 {
+	u128::MAX
+		.checked_shl(128 - u32::from(prefix.min(128)))
+		.unwrap_or(0)
+}
+
+/// Masks an IP address down to its network prefix,
+/// so that clients with a whole block of addresses count as one.
+/// IPv4-mapped IPv6 addresses are treated as IPv4.
+pub fn aggregate(ip: IpAddr, ipv4_prefix: u8, ipv6_prefix: u8) -> IpAddr {
+	match ip.to_canonical() {
+		IpAddr::V4(ipv4) => IpAddr::V4(Ipv4Addr::from(u32::from(ipv4) & ipv4_mask(ipv4_prefix))),
+		IpAddr::V6(ipv6) => IpAddr::V6(Ipv6Addr::from(u128::from(ipv6) & ipv6_mask(ipv6_prefix))),
+	}
+}
+
+/// The first and last addresses of the network containing an IP address, as stored in the database.
+/// As SQLite compares blobs byte-by-byte, this can be used to find every address in the network.
+pub fn range(ip: IpAddr, ipv4_prefix: u8, ipv6_prefix: u8) -> (Vec<u8>, Vec<u8>) {
+	match ip.to_canonical() {
+		IpAddr::V4(ipv4) => {
+			let mask = ipv4_mask(ipv4_prefix);
+			let first = u32::from(ipv4) & mask;
+			(
+				first.to_be_bytes().to_vec(),
+				(first | !mask).to_be_bytes().to_vec(),
+			)
+		}
+		IpAddr::V6(ipv6) => {
+			let mask = ipv6_mask(ipv6_prefix);
+			let first = u128::from(ipv6) & mask;
+			(
+				first.to_be_bytes().to_vec(),
+				(first | !mask).to_be_bytes().to_vec(),
+			)
+		}
+	}
+}
+
+/// Converts bytes from the database back into an IP address.
+pub fn from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+	match bytes.len() {
+		4 => <[u8; 4]>::try_from(bytes).ok().map(IpAddr::from),
+		16 => <[u8; 16]>::try_from(bytes).ok().map(IpAddr::from),
+		_ => None,
+	}
+}
+
+/// An IP address or CIDR range, such as `192.0.2.0/24`.
+/// A plain IP address is a network of just that address.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Network {
+	/// The first address of the network.
+	address: IpAddr,
+	/// The prefix length.
+	prefix: u8,
+}
+
+impl Network {
+	/// The network with the given prefix length which contains an IP address.
+	pub fn new(ip: IpAddr, prefix: u8) -> Self {
+		let address = ip.to_canonical();
+		let prefix = prefix.min(if address.is_ipv4() { 32 } else { 128 });
+		Self {
+			address: aggregate(address, prefix, prefix),
+			prefix,
+		}
+	}
+
+	/// The first address of the network.
+	pub fn address(&self) -> IpAddr {
+		self.address
+	}
+
+	/// The prefix length of the network.
+	pub fn prefix(&self) -> u8 {
+		self.prefix
+	}
+
+	/// Whether the network contains the given IP address.
+	/// IPv4-mapped IPv6 addresses are treated as IPv4.
+	pub fn contains(&self, ip: IpAddr) -> bool {
+		let ip = ip.to_canonical();
+		ip.is_ipv4() == self.address.is_ipv4()
+			&& aggregate(ip, self.prefix, self.prefix) == self.address
+	}
+}
+
+impl From<IpAddr> for Network {
+	fn from(ip: IpAddr) -> Self {
+		let address = ip.to_canonical();
+		let prefix = if address.is_ipv4() { 32 } else { 128 };
+		Self { address, prefix }
+	}
+}
+
+impl FromStr for Network {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || Error::InvalidNetwork(s.to_string());
+		let (ip, prefix) = match s.split_once('/') {
+			Some((ip, prefix)) => (
+				ip.parse::<IpAddr>().map_err(|_| invalid())?,
+				Some(prefix.parse::<u8>().map_err(|_| invalid())?),
+			),
+			None => (s.parse::<IpAddr>().map_err(|_| invalid())?, None),
+		};
+		let max = Self::from(ip).prefix;
+		match prefix {
+			Some(prefix) if prefix > max => Err(invalid()),
+			Some(prefix) => Ok(Self::new(ip, prefix)),
+			None => Ok(Self::new(ip, max)),
+		}
+	}
+}
+
+impl TryFrom<String> for Network {
+	type Error = Error;
+
+	fn try_from(s: String) -> Result<Self, Self::Error> {
+		s.parse()
+	}
+}
+
+impl fmt::Display for Network {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}/{}", self.address, self.prefix)
+	}
+}
+
+impl From<Network> for String {
+	fn from(network: Network) -> Self {
+		network.to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ip(ip: &str) -> IpAddr {
+		ip.parse().unwrap()
+	}
+
+	fn network(network: &str) -> Network {
+		network.parse().unwrap()
+	}
+
+	#[test]
+	fn parsing() {
+		assert_eq!(network("192.0.2.77/24").to_string(), "192.0.2.0/24");
+		assert_eq!(network("192.0.2.77").to_string(), "192.0.2.77/32");
+		assert_eq!(network("2001:db8::1").to_string(), "2001:db8::1/128");
+		assert_eq!(network("2001:db8::1/32").to_string(), "2001:db8::/32");
+		assert_eq!(network("::ffff:192.0.2.1").to_string(), "192.0.2.1/32");
+		for invalid in [
+			"192.0.2.0/33",
+			"2001:db8::/129",
+			"192.0.2.0/",
+			"nonsense",
+			"/8",
+		] {
+			assert!(invalid.parse::<Network>().is_err(), "{}", invalid);
+		}
+	}
+
+	#[test]
+	fn contains() {
+		let any_ipv4 = network("0.0.0.0/0");
+		assert!(any_ipv4.contains(ip("192.0.2.1")));
+		assert!(any_ipv4.contains(ip("::ffff:192.0.2.1")));
+		assert!(!any_ipv4.contains(ip("2001:db8::1")));
+		let any_ipv6 = network("::/0");
+		assert!(any_ipv6.contains(ip("2001:db8::1")));
+		assert!(!any_ipv6.contains(ip("192.0.2.1")));
+
+		let single = network("192.0.2.1/32");
+		assert!(single.contains(ip("192.0.2.1")));
+		assert!(single.contains(ip("::ffff:192.0.2.1")));
+		assert!(!single.contains(ip("192.0.2.2")));
+		let single = network("2001:db8::1/128");
+		assert!(single.contains(ip("2001:db8::1")));
+		assert!(!single.contains(ip("2001:db8::2")));
+
+		let block = network("10.0.0.0/8");
+		assert!(block.contains(ip("10.255.255.255")));
+		assert!(!block.contains(ip("11.0.0.0")));
+	}
+
+	#[test]
+	fn bytes() {
+		assert_eq!(to_bytes(ip("::ffff:192.0.2.1")), [192, 0, 2, 1]);
+		assert_eq!(to_bytes(ip("2001:db8::1")).len(), 16);
+		for address in ["192.0.2.1", "2001:db8::1"] {
+			assert_eq!(from_bytes(&to_bytes(ip(address))), Some(ip(address)));
+		}
+		assert_eq!(from_bytes(&[1, 2, 3]), None);
+	}
+
+	#[test]
+	fn aggregating() {
+		assert_eq!(aggregate(ip("192.0.2.77"), 24, 64), ip("192.0.2.0"));
+		assert_eq!(aggregate(ip("::ffff:192.0.2.77"), 24, 64), ip("192.0.2.0"));
+		assert_eq!(aggregate(ip("2001:db8::1"), 24, 64), ip("2001:db8::"));
+		assert_eq!(aggregate(ip("192.0.2.77"), 0, 0), ip("0.0.0.0"));
+		assert_eq!(aggregate(ip("2001:db8::1"), 0, 0), ip("::"));
+		assert_eq!(aggregate(ip("192.0.2.77"), 32, 128), ip("192.0.2.77"));
+		assert_eq!(aggregate(ip("2001:db8::1"), 32, 128), ip("2001:db8::1"));
+	}
+
+	#[test]
+	fn ranges() {
+		assert_eq!(
+			range(ip("192.0.2.77"), 24, 64),
+			(vec![192, 0, 2, 0], vec![192, 0, 2, 255])
+		);
+		assert_eq!(range(ip("192.0.2.77"), 0, 0), (vec![0; 4], vec![255; 4]));
+		assert_eq!(
+			range(ip("192.0.2.77"), 32, 128),
+			(vec![192, 0, 2, 77], vec![192, 0, 2, 77])
+		);
+		let (first, last) = range(ip("2001:db8::1"), 32, 64);
+		assert_eq!(from_bytes(&first), Some(ip("2001:db8::")));
+		assert_eq!(from_bytes(&last), Some(ip("2001:db8::ffff:ffff:ffff:ffff")));
+		assert_eq!(range(ip("::1"), 32, 0), (vec![0; 16], vec![255; 16]));
+		assert_eq!(
+			range(ip("::1"), 32, 128),
+			(to_bytes(ip("::1")), to_bytes(ip("::1")))
+		);
+	}
+}
@@ -0,0 +1,137 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{
+	config::{Config, LogFormat, LoggingConfig},
+	proxy,
+};
+use chrono::Utc;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::{
+	fmt::Write as _,
+	fs::{File, OpenOptions},
+	io::{LineWriter, Write as _},
+	path::Path,
+	sync::Mutex,
+};
+use tracing::{Metadata, Subscriber};
+use tracing_subscriber::{
+	filter::LevelFilter,
+	layer::{Context, Layer, SubscriberExt},
+};
+
+/// Drops warp's own request events, as we log requests ourselves, with more detail.
+struct Quiet;
+
+impl<S: Subscriber> Layer<S> for Quiet {
+	fn enabled(&self, metadata: &Metadata<'_>, _ctx: Context<'_, S>) -> bool {
+		!(metadata.is_event() && metadata.target() == "warp::filters::trace")
+	}
+}
+
+/// Sets up logging to stderr, as configured.
+pub fn init(config: &LoggingConfig) -> Result<()> {
+	let level = config
+		.level
+		.parse::<LevelFilter>()
+		.map_err(|_| eyre!("invalid log level '{}'", config.level))?;
+	let builder = tracing_subscriber::fmt()
+		.with_max_level(level)
+		.with_writer(std::io::stderr);
+	match config.format {
+		LogFormat::Pretty => tracing::subscriber::set_global_default(builder.finish().with(Quiet)),
+		// One JSON object per line, along with every span the event happened in.
+		LogFormat::Json => tracing::subscriber::set_global_default(
+			builder
+				.json()
+				.with_current_span(false)
+				.with_span_list(true)
+				.finish()
+				.with(Quiet),
+		),
+	}
+	.wrap_err("failed to set up logging")
+}
+
+/// Creates the span that everything done for a request is logged under.
+/// The route and paste ID get filled in once they're worked out.
+pub fn request_span(config: &Config, info: warp::trace::Info<'_>) -> tracing::Span {
+	let span = tracing::info_span!(
+		"request",
+		method = %info.method(),
+		path = %info.path(),
+		ip = tracing::field::Empty,
+		route = tracing::field::Empty,
+		paste = tracing::field::Empty,
+	);
+	// This is the same IP that `filter::with_ip` gives the routes.
+	if let Some(addr) = info.remote_addr() {
+		let ip = proxy::client_ip(config, addr.ip(), info.request_headers());
+		span.record("ip", tracing::field::display(ip));
+	}
+	span
+}
+
+/// Logs a finished request, and writes it to the access log, if there is one.
+pub fn request(config: &Config, access_log: Option<&AccessLog>, info: warp::log::Info<'_>) {
+	let status = info.status().as_u16();
+	let latency_us = info.elapsed().as_micros() as u64;
+	if info.status().is_server_error() {
+		tracing::error!(status, latency_us, "request failed");
+	} else {
+		tracing::info!(status, latency_us, "request finished");
+	}
+	if let Some(access_log) = access_log {
+		access_log.write(config, &info);
+	}
+}
+
+/// An access log file, in the Combined Log Format.
+pub struct AccessLog(Mutex<LineWriter<File>>);
+
+impl AccessLog {
+	/// Opens an access log file, appending to it if it already exists.
+	pub fn open(path: &Path) -> Result<Self> {
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.wrap_err_with(|| format!("failed to open access log '{}'", path.display()))?;
+		Ok(Self(Mutex::new(LineWriter::new(file))))
+	}
+
+	/// Writes a line to the access log, for a finished request.
+	fn write(&self, config: &Config, info: &warp::log::Info<'_>) {
+		// Quotes would break up the line, so escape them.
+		let quoted = |value: Option<&str>| match value {
+			Some(value) => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+			None => "\"-\"".to_string(),
+		};
+		let client = info
+			.remote_addr()
+			.map(|addr| proxy::client_ip(config, addr.ip(), info.request_headers()).to_string())
+			.unwrap_or_else(|| "-".to_string());
+		let mut line = String::new();
+		let _ = write!(
+			line,
+			"{} - - [{}] \"{} {} {:?}\" {} - {} {}",
+			client,
+			Utc::now().format("%d/%b/%Y:%H:%M:%S %z"),
+			info.method(),
+			info.path(),
+			info.version(),
+			info.status().as_u16(),
+			quoted(info.referer()),
+			quoted(info.user_agent()),
+		);
+		let mut file = self.0.lock().unwrap_or_else(|err| err.into_inner());
+		if let Err(err) = writeln!(file, "{}", line) {
+			tracing::error!(error = %err, "failed to write to access log");
+		}
+	}
+}
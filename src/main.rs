@@ -6,20 +6,55 @@
 	file, You can obtain one at http://mozilla.org/MPL/2.0/.
 */
 
+// All of our routes put together make for some very deeply nested filter types.
+#![recursion_limit = "256"]
+
+mod account;
+mod auth;
+mod backup;
+mod bans;
 mod cache;
+mod cli;
 mod config;
+mod content_filter;
 mod error;
+mod export;
 mod filter;
 mod id;
+mod ip;
+mod logging;
+mod metrics;
+mod password;
+mod pastes;
+mod privacy;
+mod proxy;
+mod quota;
+mod ratelimit;
+mod reload;
+mod reports;
 mod routes;
+mod shutdown;
 mod state;
+mod stats;
+mod storage;
+mod visibility;
 
-use crate::filter::{with_ip, with_obj};
+use crate::{
+	cli::{Args, Command},
+	config::Source,
+	filter::{
+		accounts_enabled, metered, query, rate_limited, same_origin, with_auth, with_ip, with_state,
+	},
+	ratelimit::Route,
+	routes::admin::Action,
+};
+use clap::Parser;
 use color_eyre::eyre::{Result, WrapErr};
 use futures::TryFutureExt;
-use state::State;
-use std::sync::Arc;
-use warp::{http::StatusCode, Filter};
+use shutdown::Shutdown;
+use state::{SharedState, State};
+use std::{sync::Arc, time::Duration};
+use warp::{http::StatusCode, Filter, Reply};
 
 async fn recover(
 	state: Arc<State>,
@@ -27,32 +62,37 @@ async fn recover(
 ) -> Result<impl warp::Reply, std::convert::Infallible> {
 	let status: StatusCode;
 	let response: String;
+	let kind: &'static str;
 	if rejection.is_not_found() {
+		kind = "not_found";
 		status = StatusCode::NOT_FOUND;
 		response = state
 			.handlebars
 			.render("404", &())
 			.expect("failed to render 404 page");
 	} else if let Some(err) = rejection.find::<error::Error>() {
-		status = StatusCode::INTERNAL_SERVER_ERROR;
-		response = err.to_string();
+		state.metrics.rejection(err.kind());
+		return Ok(err.response());
 	} else if let Some(err) = rejection.find::<warp::body::BodyDeserializeError>() {
+		kind = "invalid_body";
 		status = StatusCode::BAD_REQUEST;
 		response = err.to_string();
 	} else if let Some(err) = rejection.find::<warp::reject::MethodNotAllowed>() {
+		kind = "method_not_allowed";
 		status = StatusCode::BAD_REQUEST;
 		response = err.to_string();
 	} else if let Some(err) = rejection.find::<warp::reject::PayloadTooLarge>() {
+		kind = "payload_too_large";
 		status = StatusCode::BAD_REQUEST;
 		response = err.to_string();
 	} else {
+		kind = "other";
+		tracing::error!(rejection = ?rejection, "unhandled rejection");
 		status = StatusCode::INTERNAL_SERVER_ERROR;
 		response = "internal server error".to_string();
 	}
-	Ok(warp::reply::with_status(
-		warp::reply::html(response),
-		status,
-	))
+	state.metrics.rejection(kind);
+	Ok(warp::reply::with_status(warp::reply::html(response), status).into_response())
 }
 
 #[tokio::main]
@@ -60,93 +100,600 @@ async fn main() -> Result<()> {
 	// Install our fancy error handler for color-eyre.
 	color_eyre::install().wrap_err("failed to install color_eyre error handler")?;
 
+	let args = Args::parse();
+	let source = Source {
+		path: args.config,
+		..Source::default()
+	};
+	match args.command {
+		Some(Command::Serve(serve_args)) => {
+			serve(Source {
+				address: serve_args.address,
+				db_path: serve_args.db,
+				..source
+			})
+			.await
+		}
+		Some(Command::CheckConfig) => cli::check_config(&source).await,
+		Some(Command::GenKey) => cli::gen_key(),
+		Some(Command::HashPassword) => cli::hash_password().await,
+		Some(Command::Key(command)) => cli::key(&source, command).await,
+		Some(Command::User(command)) => cli::user(&source, command).await,
+		Some(Command::Ban(command)) => cli::ban(&source, command).await,
+		Some(Command::Paste(command)) => cli::paste(&source, command).await,
+		Some(Command::Export { file }) => cli::export(&source, file).await,
+		Some(Command::Import { file }) => cli::import(&source, file).await,
+		Some(Command::Backup) => cli::backup(&source).await,
+		None => serve(source).await,
+	}
+}
+
+/// Runs the web server.
+async fn serve(source: Source) -> Result<()> {
 	// Initialize our state.
-	let state = Arc::new(
-		State::new()
+	let shared = Arc::new(SharedState::new(
+		State::new(source)
 			.await
 			.wrap_err("failed to initialize sweetpaste")?,
+	));
+	// Settings that can't be reloaded are read from the state we started with.
+	let state = shared.load();
+	logging::init(&state.config.logging)?;
+	let access_log = state
+		.config
+		.logging
+		.access_log
+		.as_deref()
+		.map(logging::AccessLog::open)
+		.transpose()?
+		.map(Arc::new);
+
+	let shutdown = Shutdown::listen()?;
+	// Everything that has to finish up before we exit.
+	let mut tasks = Vec::new();
+
+	// Reload the configuration on SIGHUP.
+	tasks.push(reload::listen(shared.clone(), shutdown.clone().wait())?);
+
+	// Periodically forget about clients that are no longer rate limited.
+	tasks.push(tokio::spawn({
+		let state = state.clone();
+		let stop = shutdown.clone().wait();
+		async move {
+			tokio::pin!(stop);
+			let mut interval = tokio::time::interval(state.rate_limiter.prune_interval());
+			loop {
+				tokio::select! {
+					_ = &mut stop => break,
+					_ = interval.tick() => state.rate_limiter.prune().await,
+				}
+			}
+		}
+	}));
+
+	// Periodically erase IPs that have been kept for long enough.
+	let retention_days = state.config.privacy.ip_retention_days;
+	if retention_days > 0 {
+		tasks.push(tokio::spawn({
+			let state = state.clone();
+			let stop = shutdown.clone().wait();
+			async move {
+				tokio::pin!(stop);
+				let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+				loop {
+					tokio::select! {
+						_ = &mut stop => break,
+						_ = interval.tick() => {
+							if let Err(err) = state.storage.scrub_ips(retention_days).await {
+								tracing::error!(error = %err, "failed to erase old IPs");
+							}
+						}
+					}
+				}
+			}
+		}));
+	}
+
+	// Periodically back up the database.
+	if state.config.backups.enabled {
+		tasks.push(tokio::spawn({
+			let shared = shared.clone();
+			let stop = shutdown.clone().wait();
+			let period = Duration::from_secs(state.config.backups.interval.get());
+			async move {
+				tokio::pin!(stop);
+				// Wait a full interval first, or every restart would make a backup.
+				let mut interval =
+					tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+				loop {
+					tokio::select! {
+						_ = &mut stop => break,
+						_ = interval.tick() => {
+							// The directory and how many to keep can change with a reload.
+							let state = shared.load();
+							match backup::run(&*state.storage, &state.config.backups).await {
+								Ok((path, pruned)) => tracing::info!(
+									path = %path.display(),
+									pruned = pruned as u64,
+									"backed up database"
+								),
+								Err(err) => tracing::error!(error = %err, "failed to back up database"),
+							}
+						}
+					}
+				}
+			}
+		}));
+	}
+
+	let post = metered(
+		shared.clone(),
+		"upload",
+		warp::path::end()
+			.and(warp::post())
+			.and(with_state(shared.clone()))
+			.and(rate_limited(shared.clone(), Route::Upload))
+			.and(warp::filters::body::content_length_limit(
+				state.config.paste_limit.get() as u64,
+			))
+			.and(warp::filters::body::form::<routes::post::Upload>())
+			.and_then(|state, ip, auth, upload| {
+				routes::post::post(state, ip, auth, upload).map_err(warp::reject::custom)
+			}),
+	);
+
+	let get = metered(
+		shared.clone(),
+		"paste",
+		warp::get()
+			.and(warp::path!(String))
+			.and(with_state(shared.clone()))
+			.and(rate_limited(shared.clone(), Route::Get))
+			.and_then(|id, state, _ip, auth| {
+				routes::get::get(id, state, auth).map_err(warp::reject::custom)
+			}),
+	);
+
+	let unlock = metered(
+		shared.clone(),
+		"unlock",
+		warp::post()
+			.and(warp::path!(String))
+			.and(with_state(shared.clone()))
+			.and(rate_limited(shared.clone(), Route::Get))
+			.and(warp::filters::body::content_length_limit(4096))
+			.and(warp::filters::body::form::<routes::unlock::Unlock>())
+			.and_then(|id, state, ip, auth, unlock| {
+				routes::unlock::unlock(id, state, ip, auth, unlock).map_err(warp::reject::custom)
+			}),
+	);
+
+	let delete = metered(
+		shared.clone(),
+		"delete",
+		warp::delete()
+			.and(warp::path!(String))
+			.and(with_state(shared.clone()))
+			.and(rate_limited(shared.clone(), Route::Delete))
+			.and_then(|id, state, ip, auth| {
+				routes::delete::delete(id, state, ip, auth).map_err(warp::reject::custom)
+			}),
+	);
+
+	let delete_form = metered(
+		shared.clone(),
+		"delete_form",
+		warp::post()
+			.and(warp::path!(String / "delete"))
+			// Browsers send the admin password and session cookie along with any request, even another site's.
+			.and(same_origin(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and(rate_limited(shared.clone(), Route::Delete))
+			.and_then(|id, state, ip, auth| {
+				routes::delete::delete_form(id, state, ip, auth).map_err(warp::reject::custom)
+			}),
+	);
+
+	let login_page = metered(
+		shared.clone(),
+		"login_page",
+		warp::get()
+			.and(warp::path!("login"))
+			.and(accounts_enabled(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and_then(|state| routes::account::login_page(state).map_err(warp::reject::custom)),
+	);
+
+	let login = metered(
+		shared.clone(),
+		"login",
+		warp::post()
+			.and(warp::path!("login"))
+			.and(accounts_enabled(shared.clone()))
+			// Or another site could log people in as someone else.
+			.and(same_origin(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and(with_ip(shared.clone()))
+			.and(warp::filters::body::content_length_limit(4096))
+			.and(warp::filters::body::form::<routes::account::Credentials>())
+			.and_then(|state, ip, credentials| {
+				routes::account::login(state, ip, credentials).map_err(warp::reject::custom)
+			}),
+	);
+
+	let register_page = metered(
+		shared.clone(),
+		"register_page",
+		warp::get()
+			.and(warp::path!("register"))
+			.and(accounts_enabled(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and_then(|state| routes::account::register_page(state).map_err(warp::reject::custom)),
+	);
+
+	let register = metered(
+		shared.clone(),
+		"register",
+		warp::post()
+			.and(warp::path!("register"))
+			.and(accounts_enabled(shared.clone()))
+			.and(same_origin(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and(rate_limited(shared.clone(), Route::Register))
+			.and(warp::filters::body::content_length_limit(4096))
+			.and(warp::filters::body::form::<routes::account::Credentials>())
+			.and_then(|state, _ip, _auth, credentials| {
+				routes::account::register(state, credentials).map_err(warp::reject::custom)
+			}),
+	);
+
+	let logout = metered(
+		shared.clone(),
+		"logout",
+		warp::post()
+			.and(warp::path!("logout"))
+			.and(accounts_enabled(shared.clone()))
+			.and(same_origin(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and(warp::filters::cookie::optional::<String>(
+				account::SESSION_COOKIE,
+			))
+			.and_then(|state, session| {
+				routes::account::logout(state, session).map_err(warp::reject::custom)
+			}),
+	);
+
+	let user = metered(
+		shared.clone(),
+		"user",
+		warp::get()
+			.and(warp::path!(String))
+			// User pages look like `/~username`.
+			.and_then(|segment: String| async move {
+				match segment.strip_prefix('~') {
+					Some(username) => Ok(username.to_string()),
+					None => Err(warp::reject::not_found()),
+				}
+			})
+			.and(accounts_enabled(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and(with_auth(shared.clone()))
+			.and_then(|username, state, auth| {
+				routes::account::user(username, state, auth).map_err(warp::reject::custom)
+			}),
 	);
 
-	let post = warp::path::end()
-		.and(warp::post())
-		.and(with_obj(state.clone()))
-		.and(with_ip(state.clone()))
-		.and(warp::filters::body::content_length_limit(
-			state.config.paste_limit.get() as u64,
-		))
-		.and(warp::filters::header::optional::<String>("authorization"))
-		.and(warp::filters::body::form::<routes::post::Upload>())
-		.and_then(|state, ip, authorization, upload| {
-			routes::post::post(state, ip, authorization, upload).map_err(warp::reject::custom)
-		});
-
-	let get = warp::get()
-		.and(warp::path!(String))
-		.and(with_obj(state.clone()))
-		.and_then(|id, state| routes::get::get(id, state).map_err(warp::reject::custom));
-
-	let delete = warp::delete()
-		.and(warp::path!(String))
-		.and(with_obj(state.clone()))
-		.and(with_ip(state.clone()))
-		.and(warp::filters::header::optional::<String>("authorization"))
-		.and_then(|id, state, ip, authorization| {
-			routes::delete::delete(id, state, ip, authorization).map_err(warp::reject::custom)
-		});
-
-	let upload = warp::path::end()
-		.and(warp::get())
-		.and({
-			// Get the name of every syntax we have loaded.
-			// We do this here for efficiency - we won't need to allocate
-			// this list every time we serve a paste.
-			let mut languages = state
-				.syntax_set
-				.syntaxes()
-				.iter()
-				.map(|syntax| syntax.name.clone())
-				.collect::<Vec<String>>();
-			// Sort the syntax list.
-			languages.sort();
-			// Pre-render the page, it'll never change anyways
-			let rendered = state
-				.handlebars
-				.render(
-					"upload",
-					&serde_json::json!({ "languages": &*languages, "public": state.config.public }),
+	let bans = metered(
+		shared.clone(),
+		"bans",
+		warp::get()
+			.and(warp::path!("admin" / "bans"))
+			.and(with_state(shared.clone()))
+			.and(with_auth(shared.clone()))
+			.and_then(|state, auth| routes::admin::bans(state, auth).map_err(warp::reject::custom)),
+	);
+
+	let ban = metered(
+		shared.clone(),
+		"ban",
+		warp::post()
+			.and(warp::path!("admin" / "bans"))
+			// Browsers send the admin password and session cookie along with any request, even another site's.
+			.and(same_origin(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and(with_auth(shared.clone()))
+			.and(warp::filters::body::content_length_limit(4096))
+			.and(warp::filters::body::json::<routes::admin::NewBan>())
+			.and_then(|state, auth, new_ban| {
+				routes::admin::ban(state, auth, new_ban).map_err(warp::reject::custom)
+			}),
+	);
+
+	let ban_paste = metered(
+		shared.clone(),
+		"ban_paste",
+		warp::post()
+			.and(warp::path!("admin" / "bans" / "paste" / String))
+			.and(same_origin(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and(with_auth(shared.clone()))
+			.and(warp::filters::body::content_length_limit(4096))
+			.and(warp::filters::body::json::<routes::admin::PasteBan>())
+			.and_then(|id, state, auth, paste_ban| {
+				routes::admin::ban_paste(id, state, auth, paste_ban).map_err(warp::reject::custom)
+			}),
+	);
+
+	let lift = metered(
+		shared.clone(),
+		"lift",
+		warp::delete()
+			.and(warp::path!("admin" / "bans" / i64))
+			.and(same_origin(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and(with_auth(shared.clone()))
+			.and_then(|id, state, auth| {
+				routes::admin::lift(id, state, auth).map_err(warp::reject::custom)
+			}),
+	);
+
+	let backup = metered(
+		shared.clone(),
+		"backup",
+		warp::post()
+			.and(warp::path!("admin" / "backups"))
+			.and(same_origin(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and(with_auth(shared.clone()))
+			.and_then(|state, auth| {
+				routes::admin::backup(state, auth).map_err(warp::reject::custom)
+			}),
+	);
+
+	let report = metered(
+		shared.clone(),
+		"report",
+		warp::post()
+			.and(warp::path!(String / "report"))
+			.and(with_state(shared.clone()))
+			.and(rate_limited(shared.clone(), Route::Report))
+			.and(warp::filters::body::content_length_limit(4096))
+			.and(warp::filters::body::form::<routes::report::Report>())
+			.and_then(|id, state, ip, _auth, report| {
+				routes::report::report(id, state, ip, report).map_err(warp::reject::custom)
+			}),
+	);
+
+	let reports = metered(
+		shared.clone(),
+		"reports",
+		warp::get()
+			.and(warp::path!("admin" / "reports"))
+			.and(with_state(shared.clone()))
+			.and(with_auth(shared.clone()))
+			.and_then(|state, auth| {
+				routes::admin::reports(state, auth).map_err(warp::reject::custom)
+			}),
+	);
+
+	let moderate = metered(
+		shared.clone(),
+		"moderate",
+		warp::post()
+			.and(warp::path!("admin" / "reports" / String / Action))
+			// Admins moderate from their browser, which sends their password along with any request.
+			.and(same_origin(shared.clone()))
+			.and(with_state(shared.clone()))
+			.and(with_ip(shared.clone()))
+			.and(with_auth(shared.clone()))
+			.and_then(|id, action, state, ip, auth| {
+				routes::admin::moderate(id, action, state, ip, auth).map_err(warp::reject::custom)
+			}),
+	);
+
+	let browse = metered(
+		shared.clone(),
+		"browse",
+		warp::get()
+			.and(warp::path!("browse"))
+			.and(with_state(shared.clone()))
+			.and(rate_limited(shared.clone(), Route::Get))
+			.and(query::<routes::browse::Browse>())
+			.and_then(|state, _ip, _auth, browse| {
+				routes::browse::browse(state, browse).map_err(warp::reject::custom)
+			}),
+	);
+
+	let atom = warp::get()
+		.and(warp::path!("feed.atom"))
+		.map(|| routes::feed::Format::Atom);
+	let rss = warp::get()
+		.and(warp::path!("feed.rss"))
+		.map(|| routes::feed::Format::Rss);
+	let feed = metered(
+		shared.clone(),
+		"feed",
+		atom.or(rss)
+			.unify()
+			.and(with_state(shared.clone()))
+			.and(rate_limited(shared.clone(), Route::Get))
+			.and(query::<routes::feed::Feed>())
+			.and(warp::header::optional::<String>("if-none-match"))
+			.and(warp::header::optional::<String>("if-modified-since"))
+			.and_then(
+				|format, state, _ip, _auth, feed, if_none_match, if_modified_since| {
+					routes::feed::feed(format, state, feed, if_none_match, if_modified_since)
+						.map_err(warp::reject::custom)
+				},
+			),
+	);
+
+	let stats = metered(
+		shared.clone(),
+		"stats",
+		warp::get()
+			.and(warp::path!("stats"))
+			.and(with_state(shared.clone()))
+			.and(rate_limited(shared.clone(), Route::Get))
+			.and_then(|state, _ip, _auth| {
+				routes::stats::stats(state).map_err(warp::reject::custom)
+			}),
+	);
+
+	let stats_json = metered(
+		shared.clone(),
+		"stats_json",
+		warp::get()
+			.and(warp::path!("stats.json"))
+			.and(with_state(shared.clone()))
+			.and(rate_limited(shared.clone(), Route::Get))
+			.and_then(|state, _ip, _auth| {
+				routes::stats::stats_json(state).map_err(warp::reject::custom)
+			}),
+	);
+
+	let metrics = metered(
+		shared.clone(),
+		"metrics",
+		warp::get()
+			.and(warp::path!("metrics"))
+			.and(with_state(shared.clone()))
+			.and(with_auth(shared.clone()))
+			.and_then(|state, auth| {
+				routes::metrics::metrics(state, auth).map_err(warp::reject::custom)
+			}),
+	);
+
+	let upload = metered(
+		shared.clone(),
+		"upload_page",
+		warp::path::end()
+			.and(warp::get())
+			.and(with_state(shared.clone()))
+			// The page is rendered ahead of time, as it only changes with the configuration.
+			.map(|state: Arc<State>| warp::reply::html(state.upload_page.clone())),
+	);
+
+	// Admin, browse, feed, stats, metrics and account routes need to come first,
+	// as pastes would match `/login` and such otherwise.
+	let routes = bans
+		.or(ban)
+		.or(ban_paste)
+		.or(lift)
+		.or(backup)
+		.or(reports)
+		.or(moderate)
+		.or(browse)
+		.or(feed)
+		.or(stats)
+		.or(stats_json)
+		.or(metrics)
+		.or(login_page)
+		.or(login)
+		.or(register_page)
+		.or(register)
+		.or(logout)
+		.or(user)
+		.or(post)
+		.or(get)
+		.or(unlock)
+		.or(upload)
+		.or(delete)
+		.or(delete_form)
+		.or(report);
+
+	// Health checks skip rate limits and logging, as they're hit constantly.
+	let healthz = warp::get()
+		.and(warp::path!("healthz"))
+		.and(with_state(shared.clone()))
+		.and_then(|state| routes::health::healthz(state).map_err(warp::reject::custom));
+	let readyz = warp::get()
+		.and(warp::path!("readyz"))
+		.and(with_state(shared.clone()))
+		.and_then(|state| routes::health::readyz(state).map_err(warp::reject::custom));
+	let health = healthz.or(readyz);
+
+	// Operational endpoints get their own address, if there is one.
+	if let Some(admin_address) = state.config.admin_address {
+		let metrics = warp::get()
+			.and(warp::path!("metrics"))
+			.and(with_state(shared.clone()))
+			.and_then(|state| routes::metrics::scrape(state).map_err(warp::reject::custom));
+		let healthz = warp::get()
+			.and(warp::path!("healthz"))
+			.map(routes::health::alive);
+		let readyz = warp::get()
+			.and(warp::path!("readyz"))
+			.and(with_state(shared.clone()))
+			.and_then(|state| async move {
+				Ok::<_, warp::Rejection>(routes::health::ready(state).await)
+			});
+		let (_, server) = warp::serve(metrics.or(healthz).or(readyz).recover({
+			let shared = shared.clone();
+			move |rejection| recover(shared.load(), rejection)
+		}))
+		.bind_with_graceful_shutdown(admin_address, shutdown.clone().wait());
+		tasks.push(tokio::spawn(server));
+	}
+
+	// Log every request once it's done, under a span that everything else it logs goes under.
+	let log = warp::log::custom({
+		let shared = shared.clone();
+		move |info| logging::request(&shared.load().config, access_log.as_deref(), info)
+	});
+	let trace = warp::trace({
+		let shared = shared.clone();
+		move |info| logging::request_span(&shared.load().config, info)
+	});
+
+	// Stop accepting connections once we're told to shut down, but let in-flight requests finish.
+	let server = async {
+		match state.config.static_dir.as_ref() {
+			Some(static_dir) => {
+				warp::serve(
+					health.or(warp::filters::fs::dir(static_dir.clone())
+						.or(routes)
+						.recover({
+							let shared = shared.clone();
+							move |rejection| recover(shared.load(), rejection)
+						})
+						.with(log)
+						.with(trace)),
 				)
-				.wrap_err("failed to pre-render upload page")?;
-			with_obj(Arc::new(rendered))
-		})
-		.and_then(|rendered: Arc<String>| async move {
-			Result::<_, std::convert::Infallible>::Ok(warp::reply::html(rendered.to_string()))
-		});
-
-	match state.config.static_dir.as_ref() {
-		Some(static_dir) => {
-			warp::serve(
-				warp::filters::fs::dir(static_dir.clone())
-					.or(post)
-					.or(get)
-					.or(upload)
-					.or(delete)
-					.recover({
-						let state = state.clone();
-						move |rejection| recover(state.clone(), rejection)
-					}),
-			)
-			.run(state.config.address)
-			.await;
-		}
-		None => {
-			warp::serve(post.or(get).or(upload).or(delete).recover({
-				let state = state.clone();
-				move |rejection| recover(state.clone(), rejection)
-			}))
-			.run(state.config.address)
-			.await;
+				.bind_with_graceful_shutdown(state.config.address, shutdown.clone().wait())
+				.1
+				.await
+			}
+			None => {
+				warp::serve(
+					health.or(routes
+						.recover({
+							let shared = shared.clone();
+							move |rejection| recover(shared.load(), rejection)
+						})
+						.with(log)
+						.with(trace)),
+				)
+				.bind_with_graceful_shutdown(state.config.address, shutdown.clone().wait())
+				.1
+				.await
+			}
 		}
+	};
+
+	// Once it's time to shut down, give everything a little while to finish up.
+	let timeout = Duration::from_secs(shared.load().config.shutdown_timeout);
+	let deadline = async {
+		shutdown.clone().wait().await;
+		tokio::time::sleep(timeout).await;
+	};
+	tokio::select! {
+		_ = futures::future::join(server, futures::future::join_all(tasks)) => {}
+		_ = deadline => tracing::warn!("gave up waiting for requests to finish"),
 	}
+	// Close every database connection, which also lets SQLite checkpoint the WAL.
+	state.storage.close().await;
+	tracing::info!("shut down");
 
 	Ok(())
 }
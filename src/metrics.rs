@@ -0,0 +1,291 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::state::State;
+use std::{
+	collections::BTreeMap,
+	fmt::Write,
+	sync::{Mutex, MutexGuard},
+	time::Duration,
+};
+use warp::http::StatusCode;
+
+/// Buckets for request latency, and render time, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+	0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+/// Buckets for upload sizes, in bytes.
+const SIZE_BUCKETS: &[f64] = &[
+	256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0,
+];
+
+/// A Prometheus histogram, counting observations into buckets.
+struct Histogram {
+	/// The upper bounds of each bucket.
+	bounds: &'static [f64],
+	/// How many observations fell into each bucket, not counting the ones before it.
+	counts: Vec<u64>,
+	/// The sum of every observation.
+	sum: f64,
+	/// How many observations there have been.
+	count: u64,
+}
+
+impl Histogram {
+	fn new(bounds: &'static [f64]) -> Self {
+		Self {
+			bounds,
+			counts: vec![0; bounds.len()],
+			sum: 0.0,
+			count: 0,
+		}
+	}
+
+	/// Records an observation.
+	fn observe(&mut self, value: f64) {
+		if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+			self.counts[bucket] += 1;
+		}
+		self.sum += value;
+		self.count += 1;
+	}
+
+	/// Writes this histogram out in the Prometheus text format.
+	/// `labels` is any other labels, already formatted, like `route="get",`.
+	fn write(&self, out: &mut String, name: &str, labels: &str) {
+		let mut cumulative = 0;
+		for (bound, count) in self.bounds.iter().zip(&self.counts) {
+			cumulative += count;
+			let _ = writeln!(
+				out,
+				"{}_bucket{{{}le=\"{}\"}} {}",
+				name, labels, bound, cumulative
+			);
+		}
+		let _ = writeln!(
+			out,
+			"{}_bucket{{{}le=\"+Inf\"}} {}",
+			name, labels, self.count
+		);
+		let labels = match labels.trim_end_matches(',') {
+			"" => String::new(),
+			labels => format!("{{{}}}", labels),
+		};
+		let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+		let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+	}
+}
+
+/// Escapes a label value, for the Prometheus text format.
+fn escape(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+/// Writes the `# HELP` and `# TYPE` lines for a metric.
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+	let _ = writeln!(out, "# HELP {} {}", name, help);
+	let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+#[derive(Default)]
+struct Inner {
+	/// How many requests each route has answered, by status code.
+	requests: BTreeMap<(&'static str, u16), u64>,
+	/// How long each route takes to answer.
+	latency: BTreeMap<&'static str, Histogram>,
+	/// How big uploaded pastes are.
+	upload_sizes: Option<Histogram>,
+	/// How long highlighting takes, by language.
+	render_times: BTreeMap<String, Histogram>,
+	/// How many requests were rejected, by the kind of error.
+	rejections: BTreeMap<&'static str, u64>,
+}
+
+/// Collects metrics to be scraped by Prometheus.
+#[derive(Default)]
+pub struct Metrics(Mutex<Inner>);
+
+impl Metrics {
+	fn lock(&self) -> MutexGuard<'_, Inner> {
+		// Nothing in here can be left half-updated, so a poisoned lock is fine to keep using.
+		self.0.lock().unwrap_or_else(|err| err.into_inner())
+	}
+
+	/// Records a route answering a request.
+	pub fn request(&self, route: &'static str, status: StatusCode, elapsed: Duration) {
+		let mut inner = self.lock();
+		*inner.requests.entry((route, status.as_u16())).or_default() += 1;
+		inner
+			.latency
+			.entry(route)
+			.or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+			.observe(elapsed.as_secs_f64());
+	}
+
+	/// Records a paste being uploaded.
+	pub fn upload(&self, size: usize) {
+		self.lock()
+			.upload_sizes
+			.get_or_insert_with(|| Histogram::new(SIZE_BUCKETS))
+			.observe(size as f64);
+	}
+
+	/// Records how long a paste took to highlight.
+	pub fn render(&self, language: &str, elapsed: Duration) {
+		let mut inner = self.lock();
+		if !inner.render_times.contains_key(language) {
+			inner
+				.render_times
+				.insert(language.to_string(), Histogram::new(LATENCY_BUCKETS));
+		}
+		if let Some(histogram) = inner.render_times.get_mut(language) {
+			histogram.observe(elapsed.as_secs_f64());
+		}
+	}
+
+	/// Records a request being rejected.
+	pub fn rejection(&self, kind: &'static str) {
+		*self.lock().rejections.entry(kind).or_default() += 1;
+	}
+
+	/// Writes out every metric in the Prometheus text format.
+	pub async fn export(&self, state: &State) -> String {
+		let mut out = String::new();
+		// Grab the cache's numbers first, so we aren't holding both locks at once.
+		let (entries, weight, capacity) = {
+			let cache = state.cache.lock().await;
+			(cache.len(), cache.weight(), cache.capacity())
+		};
+		let (connections, idle) = state.storage.connections();
+		let inner = self.lock();
+
+		header(
+			&mut out,
+			"sweetpaste_requests_total",
+			"counter",
+			"Requests answered, by route and status code.",
+		);
+		for ((route, status), count) in &inner.requests {
+			let _ = writeln!(
+				out,
+				"sweetpaste_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+				route, status, count
+			);
+		}
+		header(
+			&mut out,
+			"sweetpaste_request_duration_seconds",
+			"histogram",
+			"How long requests take to answer, by route.",
+		);
+		for (route, histogram) in &inner.latency {
+			histogram.write(
+				&mut out,
+				"sweetpaste_request_duration_seconds",
+				&format!("route=\"{}\",", route),
+			);
+		}
+		header(
+			&mut out,
+			"sweetpaste_rejections_total",
+			"counter",
+			"Requests rejected with an error, by the kind of error.",
+		);
+		for (kind, count) in &inner.rejections {
+			let _ = writeln!(
+				out,
+				"sweetpaste_rejections_total{{kind=\"{}\"}} {}",
+				kind, count
+			);
+		}
+		header(
+			&mut out,
+			"sweetpaste_upload_size_bytes",
+			"histogram",
+			"How big uploaded pastes are.",
+		);
+		inner
+			.upload_sizes
+			.as_ref()
+			.unwrap_or(&Histogram::new(SIZE_BUCKETS))
+			.write(&mut out, "sweetpaste_upload_size_bytes", "");
+		header(
+			&mut out,
+			"sweetpaste_render_duration_seconds",
+			"histogram",
+			"How long pastes take to highlight, by language.",
+		);
+		for (language, histogram) in &inner.render_times {
+			histogram.write(
+				&mut out,
+				"sweetpaste_render_duration_seconds",
+				&format!("language=\"{}\",", escape(language)),
+			);
+		}
+		drop(inner);
+
+		let cache = &state.cache_stats;
+		for (name, kind, help, value) in [
+			(
+				"sweetpaste_cache_hits_total",
+				"counter",
+				"Pastes served from the cache.",
+				cache.hits() as f64,
+			),
+			(
+				"sweetpaste_cache_misses_total",
+				"counter",
+				"Pastes that weren't in the cache.",
+				cache.misses() as f64,
+			),
+			(
+				"sweetpaste_cache_evictions_total",
+				"counter",
+				"Pastes pushed out of the cache to make room.",
+				cache.evictions() as f64,
+			),
+			(
+				"sweetpaste_cache_entries",
+				"gauge",
+				"Pastes in the cache.",
+				entries as f64,
+			),
+			(
+				"sweetpaste_cache_weight_bytes",
+				"gauge",
+				"How much of the cache is used.",
+				weight as f64,
+			),
+			(
+				"sweetpaste_cache_capacity_bytes",
+				"gauge",
+				"How big the cache can get.",
+				capacity as f64,
+			),
+			(
+				"sweetpaste_db_connections",
+				"gauge",
+				"Open database connections.",
+				f64::from(connections),
+			),
+			(
+				"sweetpaste_db_connections_idle",
+				"gauge",
+				"Open database connections that aren't being used.",
+				idle as f64,
+			),
+		] {
+			header(&mut out, name, kind, help);
+			let _ = writeln!(out, "{} {}", name, value);
+		}
+		out
+	}
+}
@@ -0,0 +1,134 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::error::Error;
+use argon2::{
+	password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+	Argon2,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use subtle::ConstantTimeEq;
+
+/// Hashes a password with Argon2 and a random salt,
+/// returning the hash as a PHC-format string.
+pub async fn hash(password: String) -> Result<String, Error> {
+	// Argon2 is deliberately slow, so don't block the runtime with it.
+	tokio::task::spawn_blocking(move || {
+		let salt = SaltString::generate(&mut rand::rngs::OsRng);
+		Argon2::default()
+			.hash_password(password.as_bytes(), &salt)
+			.map(|hash| hash.to_string())
+	})
+	.await?
+	.map_err(Error::from)
+}
+
+/// Checks a password against a PHC-format hash.
+/// Malformed hashes never match.
+pub async fn verify(hash: String, password: String) -> Result<bool, Error> {
+	tokio::task::spawn_blocking(move || {
+		PasswordHash::new(&hash)
+			.map(|hash| {
+				Argon2::default()
+					.verify_password(password.as_bytes(), &hash)
+					.is_ok()
+			})
+			.unwrap_or(false)
+	})
+	.await
+	.map_err(Error::from)
+}
+
+/// The password that ships in the example configuration.
+/// sweetpaste refuses to use it unless explicitly told to.
+pub const DEFAULT_PASSWORD: &str = "hunter2";
+
+/// The instance password, as given in the configuration.
+/// This is either a PHC-format Argon2 hash, or (discouraged) plain text.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Password {
+	Hashed(String),
+	Plain(String),
+}
+
+impl Password {
+	/// Whether this is the well-known default password, either as-is or hashed.
+	pub async fn is_default(&self) -> Result<bool, Error> {
+		self.verify(DEFAULT_PASSWORD).await
+	}
+
+	/// Checks a password against this one, without leaking timing information.
+	pub async fn verify(&self, password: &str) -> Result<bool, Error> {
+		match self {
+			Self::Hashed(hash) => verify(hash.clone(), password.to_string()).await,
+			// Compare digests rather than the passwords themselves,
+			// so the comparison doesn't leak the password's length.
+			Self::Plain(plain) => Ok(Sha256::digest(plain.as_bytes())
+				.ct_eq(&Sha256::digest(password.as_bytes()))
+				.into()),
+		}
+	}
+}
+
+impl TryFrom<String> for Password {
+	type Error = String;
+
+	fn try_from(password: String) -> Result<Self, Self::Error> {
+		if !password.starts_with("$argon2") {
+			return Ok(Self::Plain(password));
+		}
+		// Looks like a hash, so make sure it actually is one.
+		match PasswordHash::new(&password) {
+			Ok(_) => Ok(Self::Hashed(password)),
+			Err(err) => Err(format!("invalid password hash: {}", err)),
+		}
+	}
+}
+
+impl From<Password> for String {
+	fn from(password: Password) -> Self {
+		match password {
+			Password::Hashed(password) | Password::Plain(password) => password,
+		}
+	}
+}
+
+impl Default for Password {
+	fn default() -> Self {
+		Self::Plain(DEFAULT_PASSWORD.to_string())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn default_password() {
+		let hashed = hash(DEFAULT_PASSWORD.to_string()).await.unwrap();
+		assert!(Password::try_from(hashed)
+			.unwrap()
+			.is_default()
+			.await
+			.unwrap());
+		assert!(Password::default().is_default().await.unwrap());
+		let hashed = hash("correct horse".to_string()).await.unwrap();
+		assert!(!Password::try_from(hashed)
+			.unwrap()
+			.is_default()
+			.await
+			.unwrap());
+		assert!(!Password::Plain("hunter3".to_string())
+			.is_default()
+			.await
+			.unwrap());
+	}
+}
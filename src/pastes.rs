@@ -0,0 +1,85 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{
+	config::Config,
+	error::Error,
+	ip::Network,
+	privacy,
+	storage::{Deleter, Storage},
+};
+
+/// A paste, as shown to an admin.
+#[derive(sqlx::FromRow)]
+pub struct Paste {
+	pub id: i64,
+	pub name: Option<String>,
+	pub syntax: Option<String>,
+	pub posted: String,
+	pub size: i64,
+	pub visibility: String,
+	pub hidden: bool,
+	pub locked: bool,
+	pub ip: Option<Vec<u8>>,
+	pub owner: Option<String>,
+}
+
+/// Narrows down which pastes are listed.
+#[derive(Default)]
+pub struct Filter {
+	/// Only pastes posted on or after this date, like `2021-08-18`.
+	pub since: Option<String>,
+	/// Only pastes posted before this date, like `2021-08-18`.
+	pub until: Option<String>,
+	/// Only pastes in this language.
+	pub language: Option<String>,
+	/// Only pastes uploaded from this IP address or range.
+	pub network: Option<Network>,
+	/// At most this many pastes.
+	pub limit: u32,
+}
+
+/// Lists pastes, newest first.
+pub async fn list(
+	storage: &dyn Storage,
+	config: &Config,
+	filter: &Filter,
+) -> Result<Vec<Paste>, Error> {
+	// Work out which stored IPs belong to the network, in whatever form they're stored.
+	let range = filter.network.map(|network| {
+		privacy::range(
+			config,
+			network.address(),
+			network.prefix(),
+			network.prefix(),
+		)
+	});
+	storage
+		.list(
+			filter,
+			range
+				.as_ref()
+				.map(|(first, last)| (first.as_slice(), last.as_slice())),
+		)
+		.await
+}
+
+/// Finds a paste, along with its content.
+pub async fn find(storage: &dyn Storage, id: i64) -> Result<Option<(Paste, String)>, Error> {
+	storage.inspect(id).await
+}
+
+/// Deletes a paste, returning whether it existed.
+pub async fn delete(storage: &dyn Storage, id: i64) -> Result<bool, Error> {
+	storage.delete(id, Deleter::anyone()).await
+}
+
+/// Deletes every paste older than the given number of days, returning how many were deleted.
+pub async fn purge(storage: &dyn Storage, days: u32) -> Result<u64, Error> {
+	storage.purge(days).await
+}
@@ -0,0 +1,81 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{
+	config::{Config, IpStorage},
+	ip::{self, Network},
+};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+
+/// Hashes an IP address, keyed with the ID key so that hashes can't be
+/// reversed by simply hashing every possible address.
+fn hash(config: &Config, ip: IpAddr) -> Vec<u8> {
+	Sha256::new()
+		.chain_update(b"sweetpaste ip hash")
+		.chain_update(config.id_key)
+		.chain_update(ip::to_bytes(ip))
+		.finalize()
+		.to_vec()
+}
+
+/// Converts an IP address into the form it's stored in the database,
+/// depending on `privacy.ip-storage`.
+pub fn store(config: &Config, ip: IpAddr) -> Vec<u8> {
+	let privacy = &config.privacy;
+	match privacy.ip_storage {
+		IpStorage::Raw => ip::to_bytes(ip),
+		IpStorage::Truncated => {
+			ip::to_bytes(ip::aggregate(ip, privacy.ipv4_prefix, privacy.ipv6_prefix))
+		}
+		IpStorage::Hashed => hash(config, ip),
+	}
+}
+
+/// The first and last stored values which belong to the network containing an IP address.
+/// Hashes can't be grouped into networks, so only the IP's own hash matches those.
+pub fn range(config: &Config, ip: IpAddr, ipv4_prefix: u8, ipv6_prefix: u8) -> (Vec<u8>, Vec<u8>) {
+	let privacy = &config.privacy;
+	match privacy.ip_storage {
+		IpStorage::Raw => ip::range(ip, ipv4_prefix, ipv6_prefix),
+		// Truncated IPs can't be told apart any more finely than they're stored.
+		IpStorage::Truncated => ip::range(
+			ip,
+			ipv4_prefix.min(privacy.ipv4_prefix),
+			ipv6_prefix.min(privacy.ipv6_prefix),
+		),
+		IpStorage::Hashed => {
+			let hash = hash(config, ip);
+			(hash.clone(), hash)
+		}
+	}
+}
+
+/// The network that a stored IP came from, if it can be worked out.
+/// Hashed IPs can't be.
+pub fn network(config: &Config, stored: &[u8]) -> Option<Network> {
+	let privacy = &config.privacy;
+	let ip = ip::from_bytes(stored)?;
+	Some(match privacy.ip_storage {
+		IpStorage::Truncated if ip.is_ipv4() => Network::new(ip, privacy.ipv4_prefix),
+		IpStorage::Truncated => Network::new(ip, privacy.ipv6_prefix),
+		_ => Network::from(ip),
+	})
+}
+
+/// Shows a stored IP to an admin.
+/// Hashed IPs are shortened, as they're only good for telling uploaders apart.
+pub fn display(config: &Config, stored: &[u8]) -> String {
+	match network(config, stored) {
+		Some(network) if network == Network::from(network.address()) => {
+			network.address().to_string()
+		}
+		Some(network) => network.to_string(),
+		None => hex::encode(&stored[..stored.len().min(8)]),
+	}
+}
@@ -0,0 +1,249 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::config::{Config, ProxyHeader};
+use std::net::{IpAddr, Ipv4Addr};
+use warp::http::HeaderMap;
+
+impl ProxyHeader {
+	/// The name of the header.
+	fn name(self) -> &'static str {
+		match self {
+			Self::Forwarded => "forwarded",
+			Self::XForwardedFor => "x-forwarded-for",
+			Self::XRealIp => "x-real-ip",
+			Self::CfConnectingIp => "cf-connecting-ip",
+		}
+	}
+}
+
+/// Parses a node from a proxy header, which may have a port attached,
+/// like `192.0.2.1:4711` or `[2001:db8::1]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+	let node = node.trim().trim_matches('"');
+	if let Some(bracketed) = node.strip_prefix('[') {
+		return bracketed.split(']').next()?.parse().ok();
+	}
+	node.parse().ok().or_else(|| {
+		// Only IPv4 addresses can have a port without brackets.
+		let (host, port) = node.rsplit_once(':')?;
+		port.parse::<u16>().ok()?;
+		host.parse::<Ipv4Addr>().ok().map(IpAddr::V4)
+	})
+}
+
+/// Parses the `for` parameter from an element of a `Forwarded` header,
+/// like `for=192.0.2.60;proto=http;by=203.0.113.43`.
+/// Obfuscated or `unknown` nodes aren't IP addresses, so they come back as [None].
+fn parse_forwarded(element: &str) -> Option<IpAddr> {
+	element
+		.split(';')
+		.filter_map(|pair| pair.split_once('='))
+		.find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+		.and_then(|(_, value)| parse_node(value))
+}
+
+/// Works out the client's IP address, from the address the request came from and its headers.
+///
+/// Headers are only believed if the request came from a trusted proxy.
+/// Forwarding chains are walked from the right, skipping over trusted proxies,
+/// as anything to the left of the first untrusted hop could've been made up by the client.
+pub fn client_ip(config: &Config, origin: IpAddr, headers: &HeaderMap) -> IpAddr {
+	let trusted = |ip: IpAddr| {
+		config
+			.trusted_ips
+			.iter()
+			.any(|network| network.contains(ip))
+	};
+	if !trusted(origin) {
+		return origin;
+	}
+	for header in config.proxy_headers.iter().copied() {
+		// Proxies may either append to a header, or add another one, so look at all of them.
+		let values = headers
+			.get_all(header.name())
+			.iter()
+			.filter_map(|value| value.to_str().ok())
+			.collect::<Vec<_>>();
+		if values.is_empty() {
+			continue;
+		}
+		match header {
+			ProxyHeader::Forwarded | ProxyHeader::XForwardedFor => {
+				let hops = values.iter().flat_map(|value| value.split(',')).map(|hop| {
+					if header == ProxyHeader::Forwarded {
+						parse_forwarded(hop)
+					} else {
+						parse_node(hop)
+					}
+				});
+				let mut client = origin;
+				for hop in hops.rev() {
+					match hop {
+						Some(ip) => {
+							client = ip;
+							if !trusted(ip) {
+								break;
+							}
+						}
+						// We can't see past a hop we don't understand,
+						// so the last one we trusted will have to do.
+						None => break,
+					}
+				}
+				return client;
+			}
+			ProxyHeader::XRealIp | ProxyHeader::CfConnectingIp => {
+				if let Some(ip) = values.last().and_then(|value| parse_node(value)) {
+					return ip;
+				}
+			}
+		}
+	}
+	origin
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use warp::http::HeaderValue;
+
+	/// A configuration trusting proxies in `10.0.0.0/8` and `fd00::/8`.
+	fn config() -> Config {
+		Config {
+			trusted_ips: vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()],
+			..Config::default()
+		}
+	}
+
+	fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+		let mut map = HeaderMap::new();
+		for (name, value) in headers {
+			map.append(*name, HeaderValue::from_static(value));
+		}
+		map
+	}
+
+	fn ip(ip: &str) -> IpAddr {
+		ip.parse().unwrap()
+	}
+
+	#[test]
+	fn untrusted_origins_are_taken_at_their_word() {
+		let headers = headers(&[("x-forwarded-for", "192.0.2.1"), ("x-real-ip", "192.0.2.1")]);
+		assert_eq!(
+			client_ip(&config(), ip("203.0.113.9"), &headers),
+			ip("203.0.113.9")
+		);
+	}
+
+	#[test]
+	fn spoofed_leading_hops_are_ignored() {
+		let headers = headers(&[("x-forwarded-for", "192.0.2.1, 203.0.113.9, 10.0.0.2")]);
+		assert_eq!(
+			client_ip(&config(), ip("10.0.0.1"), &headers),
+			ip("203.0.113.9")
+		);
+	}
+
+	#[test]
+	fn chains_can_be_split_across_headers() {
+		let headers = headers(&[
+			("x-forwarded-for", "192.0.2.1"),
+			("x-forwarded-for", "203.0.113.9, 10.0.0.2"),
+		]);
+		assert_eq!(
+			client_ip(&config(), ip("10.0.0.1"), &headers),
+			ip("203.0.113.9")
+		);
+	}
+
+	#[test]
+	fn unreadable_hops_stop_the_walk() {
+		let headers = headers(&[("x-forwarded-for", "203.0.113.9, nonsense, 10.0.0.2")]);
+		assert_eq!(
+			client_ip(&config(), ip("10.0.0.1"), &headers),
+			ip("10.0.0.2")
+		);
+	}
+
+	#[test]
+	fn a_chain_of_proxies_ends_at_the_first() {
+		let headers = headers(&[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]);
+		assert_eq!(
+			client_ip(&config(), ip("10.0.0.1"), &headers),
+			ip("10.0.0.3")
+		);
+	}
+
+	#[test]
+	fn ipv6_and_ports() {
+		let headers = headers(&[(
+			"x-forwarded-for",
+			"[2001:db8::1]:4711, 192.0.2.1:80, fd00::2",
+		)]);
+		assert_eq!(
+			client_ip(&config(), ip("fd00::1"), &headers),
+			ip("192.0.2.1")
+		);
+		let headers = self::headers(&[("x-forwarded-for", "[2001:db8::1]:4711, fd00::2")]);
+		assert_eq!(
+			client_ip(&config(), ip("fd00::1"), &headers),
+			ip("2001:db8::1")
+		);
+		assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+		assert_eq!(parse_node("2001:db8::1:80"), Some(ip("2001:db8::1:80")));
+		assert_eq!(parse_node("192.0.2.1:99999"), None);
+	}
+
+	#[test]
+	fn ipv4_mapped_proxies_are_trusted() {
+		let headers = headers(&[("x-forwarded-for", "203.0.113.9, ::ffff:10.0.0.2")]);
+		assert_eq!(
+			client_ip(&config(), ip("::ffff:10.0.0.1"), &headers),
+			ip("203.0.113.9")
+		);
+	}
+
+	#[test]
+	fn forwarded() {
+		let headers = headers(&[(
+			"forwarded",
+			"for=192.0.2.1, for=\"[2001:db8::1]:4711\";proto=https, For=10.0.0.2;by=10.0.0.1",
+		)]);
+		assert_eq!(
+			client_ip(&config(), ip("10.0.0.1"), &headers),
+			ip("2001:db8::1")
+		);
+		assert_eq!(parse_forwarded("for=_hidden;proto=http"), None);
+		assert_eq!(parse_forwarded("for=unknown"), None);
+	}
+
+	#[test]
+	fn headers_are_tried_in_order() {
+		let headers = headers(&[
+			("x-real-ip", "192.0.2.1"),
+			("x-forwarded-for", "203.0.113.9"),
+		]);
+		assert_eq!(
+			client_ip(&config(), ip("10.0.0.1"), &headers),
+			ip("203.0.113.9")
+		);
+		let config = Config {
+			proxy_headers: vec![ProxyHeader::XRealIp],
+			..config()
+		};
+		assert_eq!(
+			client_ip(&config, ip("10.0.0.1"), &headers),
+			ip("192.0.2.1")
+		);
+		// Headers that aren't configured are ignored.
+		let headers = self::headers(&[("cf-connecting-ip", "192.0.2.1")]);
+		assert_eq!(client_ip(&config, ip("10.0.0.1"), &headers), ip("10.0.0.1"));
+	}
+}
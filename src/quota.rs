@@ -0,0 +1,78 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{auth::Auth, privacy, state::State, storage::Usage};
+use std::{convert::TryFrom, net::IpAddr};
+
+/// What's left of an uploader's quota, after their upload.
+/// [None] means there's no limit.
+pub struct Remaining {
+	pub pastes: Option<u64>,
+	pub bytes: Option<u64>,
+}
+
+/// Subtracts `used` from `limit`, where a limit of zero means unlimited.
+fn remaining(limit: u64, used: u64) -> Option<u64> {
+	if limit == 0 {
+		None
+	} else {
+		Some(limit.saturating_sub(used))
+	}
+}
+
+/// How much an uploader may upload from their IP (or IP block) within the quota window.
+pub struct Quota {
+	/// The range of stored IPs that count towards the same quota.
+	pub first: Vec<u8>,
+	pub last: Vec<u8>,
+	/// How many hours back uploads are counted for.
+	pub hours: u32,
+	/// The most pastes, and bytes, that may be uploaded within the window. Zero is unlimited.
+	pub max_pastes: u64,
+	pub max_bytes: u64,
+}
+
+impl Quota {
+	/// The quota for an uploader, or [None] if quotas are disabled.
+	pub fn new(state: &State, ip: IpAddr, auth: &Auth) -> Option<Self> {
+		let config = &state.config.quota;
+		if !config.enabled {
+			return None;
+		}
+		let (max_pastes, max_bytes) = if auth.is_trusted() {
+			(config.authorized_max_pastes, config.authorized_max_bytes)
+		} else {
+			(config.max_pastes, config.max_bytes)
+		};
+		let (first, last) =
+			privacy::range(&state.config, ip, config.ipv4_prefix, config.ipv6_prefix);
+		Some(Self {
+			first,
+			last,
+			hours: config.window_hours.get(),
+			max_pastes,
+			max_bytes,
+		})
+	}
+
+	/// Whether everything uploaded within the window, including the new upload, fits.
+	pub fn allows(&self, usage: &Usage) -> bool {
+		let pastes = u64::try_from(usage.pastes).unwrap_or(0);
+		let bytes = u64::try_from(usage.bytes).unwrap_or(0);
+		let over = |limit: u64, used: u64| limit != 0 && used > limit;
+		!over(self.max_pastes, pastes) && !over(self.max_bytes, bytes)
+	}
+
+	/// What's left of the quota, given everything uploaded within the window, including the new upload.
+	pub fn remaining(&self, usage: &Usage) -> Remaining {
+		Remaining {
+			pastes: remaining(self.max_pastes, u64::try_from(usage.pastes).unwrap_or(0)),
+			bytes: remaining(self.max_bytes, u64::try_from(usage.bytes).unwrap_or(0)),
+		}
+	}
+}
@@ -0,0 +1,310 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{
+	config::{BucketConfig, RateLimitConfig},
+	error::Error,
+	ip,
+};
+use fnv::FnvHashMap;
+use std::{
+	net::IpAddr,
+	time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+/// Which set of limits a request counts against.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Route {
+	/// Uploading a paste.
+	Upload,
+	/// Viewing a paste.
+	Get,
+	/// Deleting a paste.
+	Delete,
+	/// Reporting a paste.
+	Report,
+	/// Registering an account.
+	Register,
+	/// Getting a password or API key wrong.
+	FailedAuth,
+}
+
+/// A token bucket, which refills over time.
+struct Bucket {
+	/// How many requests can be made right now.
+	tokens: f64,
+	/// When `tokens` was last brought up to date.
+	updated: Instant,
+}
+
+impl Bucket {
+	/// Adds any tokens that have accumulated since the last update.
+	fn refill(&mut self, config: BucketConfig) {
+		let now = Instant::now();
+		let elapsed = now.duration_since(self.updated).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * config.per_second()).min(config.capacity as f64);
+		self.updated = now;
+	}
+
+	/// How long until there's a whole token in the bucket.
+	fn retry_after(&self, config: BucketConfig) -> Duration {
+		Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / config.per_second())
+	}
+}
+
+/// Limits how many requests each IP (or IP block) can make.
+pub struct RateLimiter {
+	config: RateLimitConfig,
+	buckets: Mutex<FnvHashMap<(Route, IpAddr), Bucket>>,
+}
+
+impl RateLimiter {
+	pub fn new(config: &RateLimitConfig) -> Self {
+		Self {
+			config: config.clone(),
+			buckets: Mutex::new(FnvHashMap::default()),
+		}
+	}
+
+	/// The limits for the given route, or [None] if it isn't limited.
+	/// Failed authentication and registration are always limited, even if nothing else is,
+	/// as they hash passwords.
+	fn bucket_config(&self, route: Route) -> Option<BucketConfig> {
+		match route {
+			Route::FailedAuth => Some(self.config.failed_auth),
+			Route::Register => Some(self.config.register),
+			_ if !self.config.enabled => None,
+			Route::Upload => Some(self.config.upload),
+			Route::Get => Some(self.config.get),
+			Route::Delete => Some(self.config.delete),
+			Route::Report => Some(self.config.report),
+		}
+	}
+
+	/// Whether authorized API keys skip the limits for this route.
+	pub fn exempts_authorized(&self, route: Route) -> bool {
+		self.config.exempt_authorized && !matches!(route, Route::FailedAuth | Route::Register)
+	}
+
+	/// The bucket an IP's requests to a route count against.
+	fn key(&self, route: Route, ip: IpAddr) -> (Route, IpAddr) {
+		(
+			route,
+			ip::aggregate(ip, self.config.ipv4_prefix, self.config.ipv6_prefix),
+		)
+	}
+
+	/// Takes a token from an IP's bucket, if there's one to take.
+	async fn take_or_check(&self, route: Route, ip: IpAddr, take: bool) -> Result<(), Error> {
+		let config = match self.bucket_config(route) {
+			Some(config) => config,
+			None => return Ok(()),
+		};
+		let key = self.key(route, ip);
+		let mut buckets = self.buckets.lock().await;
+		let bucket = buckets.entry(key).or_insert_with(|| Bucket {
+			tokens: config.capacity as f64,
+			updated: Instant::now(),
+		});
+		bucket.refill(config);
+		if bucket.tokens < 1.0 {
+			return Err(Error::RateLimited(bucket.retry_after(config)));
+		}
+		if take {
+			bucket.tokens -= 1.0;
+		}
+		Ok(())
+	}
+
+	/// Counts a request against an IP's limits,
+	/// returning [Error::RateLimited] if it's over them.
+	pub async fn take(&self, route: Route, ip: IpAddr) -> Result<(), Error> {
+		self.take_or_check(route, ip, true).await
+	}
+
+	/// Checks whether an IP is over its limits, without counting a request.
+	pub async fn check(&self, route: Route, ip: IpAddr) -> Result<(), Error> {
+		self.take_or_check(route, ip, false).await
+	}
+
+	/// Gives back a token taken by [RateLimiter::take], for a request that turned out not to count.
+	pub async fn refund(&self, route: Route, ip: IpAddr) {
+		let config = match self.bucket_config(route) {
+			Some(config) => config,
+			None => return,
+		};
+		if let Some(bucket) = self.buckets.lock().await.get_mut(&self.key(route, ip)) {
+			bucket.refill(config);
+			bucket.tokens = (bucket.tokens + 1.0).min(config.capacity as f64);
+		}
+	}
+
+	/// Forgets about any buckets that have refilled completely,
+	/// as they're no different from a new one.
+	pub async fn prune(&self) {
+		let mut buckets = self.buckets.lock().await;
+		buckets.retain(|(route, _), bucket| match self.bucket_config(*route) {
+			Some(config) => {
+				bucket.refill(config);
+				bucket.tokens < config.capacity as f64
+			}
+			None => false,
+		});
+	}
+
+	/// How often [RateLimiter::prune] should be run.
+	pub fn prune_interval(&self) -> Duration {
+		Duration::from_secs(self.config.prune_interval.get())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::num::NonZeroU32;
+
+	fn bucket_config(capacity: u32, per_minute: u32) -> BucketConfig {
+		BucketConfig {
+			capacity,
+			per_minute: NonZeroU32::new(per_minute).unwrap(),
+		}
+	}
+
+	/// A bucket with some tokens, last updated a while ago.
+	fn bucket(tokens: f64, ago: Duration) -> Bucket {
+		Bucket {
+			tokens,
+			updated: Instant::now() - ago,
+		}
+	}
+
+	#[test]
+	fn refill() {
+		let config = bucket_config(10, 60);
+		let mut empty = bucket(0.0, Duration::from_secs(3));
+		empty.refill(config);
+		assert!((3.0..3.5).contains(&empty.tokens));
+		// Full buckets stay at their capacity, however long they've been left.
+		let mut full = bucket(10.0, Duration::from_secs(3600));
+		full.refill(config);
+		assert!((full.tokens - 10.0).abs() < f64::EPSILON);
+		let mut nearly_full = bucket(9.5, Duration::from_secs(1));
+		nearly_full.refill(config);
+		assert!((nearly_full.tokens - 10.0).abs() < f64::EPSILON);
+	}
+
+	#[test]
+	fn retry_after() {
+		let config = bucket_config(10, 30);
+		assert_eq!(
+			bucket(0.0, Duration::ZERO).retry_after(config),
+			Duration::from_secs(2)
+		);
+		assert_eq!(
+			bucket(0.5, Duration::ZERO).retry_after(config),
+			Duration::from_secs(1)
+		);
+		assert_eq!(
+			bucket(1.0, Duration::ZERO).retry_after(config),
+			Duration::ZERO
+		);
+	}
+
+	/// A limiter allowing two uploads, then one an hour.
+	fn limiter(enabled: bool) -> RateLimiter {
+		RateLimiter::new(&RateLimitConfig {
+			enabled,
+			upload: bucket_config(2, 1),
+			failed_auth: bucket_config(1, 1),
+			..RateLimitConfig::default()
+		})
+	}
+
+	#[tokio::test]
+	async fn limits() {
+		let limiter = limiter(true);
+		let ip = "192.0.2.1".parse().unwrap();
+		limiter.check(Route::Upload, ip).await.unwrap();
+		limiter.take(Route::Upload, ip).await.unwrap();
+		limiter.take(Route::Upload, ip).await.unwrap();
+		assert!(matches!(
+			limiter.take(Route::Upload, ip).await,
+			Err(Error::RateLimited(retry_after)) if retry_after > Duration::from_secs(50)
+		));
+		assert!(limiter.check(Route::Upload, ip).await.is_err());
+		// Other routes and clients have their own buckets.
+		limiter.take(Route::Get, ip).await.unwrap();
+		limiter
+			.take(Route::Upload, "192.0.2.2".parse().unwrap())
+			.await
+			.unwrap();
+		// Refunds give a token back, but never go over the capacity.
+		limiter.refund(Route::Upload, ip).await;
+		limiter.take(Route::Upload, ip).await.unwrap();
+		let other = "192.0.2.3".parse().unwrap();
+		limiter.refund(Route::Upload, other).await;
+		limiter.refund(Route::Upload, other).await;
+		limiter.take(Route::Upload, other).await.unwrap();
+		limiter.take(Route::Upload, other).await.unwrap();
+		assert!(limiter.take(Route::Upload, other).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn ipv6_clients_share_their_prefix() {
+		let limiter = limiter(true);
+		limiter
+			.take(Route::Upload, "2001:db8::1".parse().unwrap())
+			.await
+			.unwrap();
+		limiter
+			.take(Route::Upload, "2001:db8::ffff".parse().unwrap())
+			.await
+			.unwrap();
+		assert!(limiter
+			.take(Route::Upload, "2001:db8::2".parse().unwrap())
+			.await
+			.is_err());
+		limiter
+			.take(Route::Upload, "2001:db8:0:1::1".parse().unwrap())
+			.await
+			.unwrap();
+	}
+
+	#[tokio::test]
+	async fn password_hashing_is_always_limited() {
+		let limiter = limiter(false);
+		let ip = "192.0.2.1".parse().unwrap();
+		for _ in 0..10 {
+			limiter.take(Route::Upload, ip).await.unwrap();
+		}
+		limiter.take(Route::FailedAuth, ip).await.unwrap();
+		assert!(limiter.take(Route::FailedAuth, ip).await.is_err());
+		for _ in 0..3 {
+			limiter.take(Route::Register, ip).await.unwrap();
+		}
+		assert!(limiter.take(Route::Register, ip).await.is_err());
+		assert!(!limiter.exempts_authorized(Route::FailedAuth));
+		assert!(!limiter.exempts_authorized(Route::Register));
+		assert!(limiter.exempts_authorized(Route::Upload));
+	}
+
+	#[tokio::test]
+	async fn prune() {
+		let limiter = limiter(true);
+		let ip = "192.0.2.1".parse().unwrap();
+		limiter.take(Route::Upload, ip).await.unwrap();
+		limiter.refund(Route::Upload, ip).await;
+		limiter.take(Route::Get, ip).await.unwrap();
+		limiter.prune().await;
+		// The upload bucket is full again, so there's no need to keep it.
+		let buckets = limiter.buckets.lock().await;
+		assert_eq!(buckets.len(), 1);
+		assert!(buckets.contains_key(&(Route::Get, ip)));
+	}
+}
@@ -0,0 +1,58 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::state::SharedState;
+use color_eyre::eyre::Result;
+use std::{future::Future, sync::Arc};
+use tokio::task::JoinHandle;
+
+/// Reloads the configuration whenever we get a SIGHUP, until `stop` finishes.
+#[cfg(unix)]
+pub fn listen(
+	state: Arc<SharedState>,
+	stop: impl Future<Output = ()> + Send + 'static,
+) -> Result<JoinHandle<()>> {
+	use color_eyre::eyre::WrapErr;
+	use tokio::signal::unix::{signal, SignalKind};
+	let mut hangup = signal(SignalKind::hangup()).wrap_err("failed to listen for SIGHUP")?;
+	Ok(tokio::spawn(async move {
+		tokio::pin!(stop);
+		loop {
+			tokio::select! {
+				_ = &mut stop => break,
+				_ = hangup.recv() => reload(&state).await,
+			}
+		}
+	}))
+}
+
+/// There's no SIGHUP to listen for here, so this does nothing.
+#[cfg(not(unix))]
+pub fn listen(
+	_state: Arc<SharedState>,
+	_stop: impl Future<Output = ()> + Send + 'static,
+) -> Result<JoinHandle<()>> {
+	Ok(tokio::spawn(async {}))
+}
+
+/// Reloads the configuration, logging how it went.
+/// If anything fails to load, the old configuration is kept as it was.
+pub async fn reload(state: &SharedState) {
+	match state.reload().await {
+		Ok(fixed) => {
+			for setting in fixed {
+				tracing::warn!(setting, "this setting can't be changed without a restart");
+			}
+			tracing::info!("reloaded configuration");
+		}
+		Err(err) => {
+			// The alternate form gives the whole chain of causes on one line.
+			tracing::error!(error = %format_args!("{:#}", err), "failed to reload configuration, keeping the old one");
+		}
+	}
+}
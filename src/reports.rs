@@ -0,0 +1,19 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+/// A paste waiting for moderation, along with its reports.
+#[derive(sqlx::FromRow)]
+pub struct Reported {
+	pub id: i64,
+	pub name: Option<String>,
+	pub ip: Option<Vec<u8>>,
+	pub hidden: bool,
+	pub preview: String,
+	pub reports: i64,
+	pub reasons: String,
+}
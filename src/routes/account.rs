@@ -0,0 +1,213 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{
+	account::{self, SESSION_COOKIE},
+	auth::Auth,
+	error::Error,
+	id::Id,
+	ratelimit::Route,
+	routes::get::not_found,
+	state::State,
+};
+use serde::Deserialize;
+use std::{net::IpAddr, sync::Arc};
+use warp::{
+	http::{header, Response, StatusCode},
+	Reply,
+};
+
+#[derive(Deserialize)]
+pub struct Credentials {
+	username: String,
+	password: String,
+}
+
+/// Builds a "303 See Other" redirect, optionally setting a cookie.
+pub(crate) fn see_other(location: String, cookie: Option<String>) -> warp::reply::Response {
+	let mut response = Response::builder()
+		.status(StatusCode::SEE_OTHER)
+		.header(header::LOCATION, location);
+	if let Some(cookie) = cookie {
+		response = response.header(header::SET_COOKIE, cookie);
+	}
+	response
+		.body(warp::hyper::Body::empty())
+		.unwrap_or_else(|_| unreachable!())
+}
+
+/// Builds the `Set-Cookie` value for a session token.
+fn session_cookie(state: &State, token: &str, max_age: u64) -> String {
+	let secure = if state.config.site_url.starts_with("https://") {
+		"; Secure"
+	} else {
+		""
+	};
+	format!(
+		"{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+		SESSION_COOKIE, token, max_age, secure
+	)
+}
+
+/// Renders the login (or registration) form.
+fn render_form(
+	state: &State,
+	register: bool,
+	error: Option<String>,
+	status: StatusCode,
+) -> Result<warp::reply::Response, Error> {
+	let rendered = state.handlebars.render(
+		"login",
+		&serde_json::json!({
+			"register": register,
+			"registration": state.config.accounts.registration,
+			"error": error,
+		}),
+	)?;
+	Ok(warp::reply::with_status(warp::reply::html(rendered), status).into_response())
+}
+
+/// Logs a user in, and sends them to their paste list.
+async fn start_session(
+	state: &State,
+	user: &account::User,
+) -> Result<warp::reply::Response, Error> {
+	let days = state.config.accounts.session_days;
+	let token = account::create_session(&*state.storage, user, days).await?;
+	Ok(see_other(
+		format!("{}/~{}", state.config.site_url, user.username),
+		Some(session_cookie(state, &token, u64::from(days) * 86400)),
+	))
+}
+
+pub async fn login_page(state: Arc<State>) -> Result<warp::reply::Response, Error> {
+	render_form(&state, false, None, StatusCode::OK)
+}
+
+pub async fn login(
+	state: Arc<State>,
+	ip: IpAddr,
+	credentials: Credentials,
+) -> Result<warp::reply::Response, Error> {
+	// Count this as a failed guess up front, as checking the password is expensive,
+	// and concurrent guesses would all get past the limit before any of them were counted.
+	state.rate_limiter.take(Route::FailedAuth, ip).await?;
+	match account::login(&*state.storage, &credentials.username, credentials.password).await? {
+		Some(user) => {
+			// It wasn't a failed guess after all.
+			state.rate_limiter.refund(Route::FailedAuth, ip).await;
+			start_session(&state, &user).await
+		}
+		None => render_form(
+			&state,
+			false,
+			Some("Incorrect username or password.".to_string()),
+			StatusCode::FORBIDDEN,
+		),
+	}
+}
+
+pub async fn register_page(state: Arc<State>) -> Result<warp::reply::Response, Error> {
+	if !state.config.accounts.registration {
+		return render_form(
+			&state,
+			true,
+			Some("Registration is closed.".to_string()),
+			StatusCode::FORBIDDEN,
+		);
+	}
+	render_form(&state, true, None, StatusCode::OK)
+}
+
+pub async fn register(
+	state: Arc<State>,
+	credentials: Credentials,
+) -> Result<warp::reply::Response, Error> {
+	if !state.config.accounts.registration {
+		return register_page(state).await;
+	}
+	if !account::valid_username(&credentials.username) {
+		return render_form(
+			&state,
+			true,
+			Some(Error::InvalidUsername.to_string()),
+			StatusCode::BAD_REQUEST,
+		);
+	}
+	if credentials.password.is_empty() {
+		return render_form(
+			&state,
+			true,
+			Some("You need a password.".to_string()),
+			StatusCode::BAD_REQUEST,
+		);
+	}
+	if !account::create_user(&*state.storage, &credentials.username, credentials.password).await? {
+		return render_form(
+			&state,
+			true,
+			Some("That username is taken.".to_string()),
+			StatusCode::CONFLICT,
+		);
+	}
+	match account::find_user(&*state.storage, &credentials.username).await? {
+		Some(user) => start_session(&state, &user).await,
+		None => Err(Error::InvalidUsername),
+	}
+}
+
+pub async fn logout(
+	state: Arc<State>,
+	session: Option<String>,
+) -> Result<warp::reply::Response, Error> {
+	if let Some(token) = session {
+		account::delete_session(&*state.storage, &token).await?;
+	}
+	Ok(see_other(
+		state.config.site_url.clone(),
+		Some(session_cookie(&state, "", 0)),
+	))
+}
+
+/// Lists a user's pastes.
+pub async fn user(
+	username: String,
+	state: Arc<State>,
+	auth: Auth,
+) -> Result<warp::reply::Response, Error> {
+	let user = match account::find_user(&*state.storage, &username).await? {
+		Some(user) => user,
+		None => return Ok(not_found(&state)?.into_response()),
+	};
+	// Owners get to see all of their pastes, along with delete buttons and a logout button.
+	// Everyone else only sees the public ones.
+	let own = auth
+		.user
+		.map(|viewer| viewer.id == user.id)
+		.unwrap_or(false);
+	let pastes = state.storage.owned(user.id, own).await?;
+	let mut listing = Vec::with_capacity(pastes.len());
+	for paste in pastes {
+		listing.push(serde_json::json!({
+			"id": Id::from(paste.id).encode(&state).await,
+			"name": paste.name,
+			"language": paste.syntax.unwrap_or_else(|| "Plain Text".to_string()),
+			"posted": paste.posted,
+			"visibility": paste.visibility,
+		}));
+	}
+	let rendered = state.handlebars.render(
+		"user",
+		&serde_json::json!({
+			"username": user.username,
+			"pastes": listing,
+			"own": own,
+		}),
+	)?;
+	Ok(warp::reply::with_status(warp::reply::html(rendered), StatusCode::OK).into_response())
+}
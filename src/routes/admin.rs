@@ -0,0 +1,198 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{
+	auth::{Auth, Scope},
+	backup, bans,
+	error::Error,
+	id::Id,
+	privacy,
+	routes::{account::see_other, delete},
+	state::State,
+};
+use serde::Deserialize;
+use std::{net::IpAddr, str::FromStr, sync::Arc};
+use warp::{http::StatusCode, Reply};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NewBan {
+	network: String,
+	reason: Option<String>,
+	expires_in_days: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PasteBan {
+	reason: Option<String>,
+	expires_in_days: Option<u32>,
+}
+
+/// Rejects requests without the `admin` scope.
+/// Requests without any authorization at all are asked for it.
+pub(crate) fn require_admin(auth: &Auth) -> Result<(), Error> {
+	if auth.can(Scope::Admin) {
+		Ok(())
+	} else if auth.is_authorized() {
+		Err(Error::Forbidden)
+	} else {
+		Err(Error::Unauthorized)
+	}
+}
+
+/// Lists every active ban, as JSON.
+pub async fn bans(state: Arc<State>, auth: Auth) -> Result<warp::reply::Response, Error> {
+	require_admin(&auth)?;
+	let bans = state.storage.bans().await?;
+	Ok(warp::reply::json(&bans).into_response())
+}
+
+/// Bans an IP address or CIDR range.
+pub async fn ban(
+	state: Arc<State>,
+	auth: Auth,
+	new_ban: NewBan,
+) -> Result<warp::reply::Response, Error> {
+	require_admin(&auth)?;
+	let ban = bans::add(
+		&*state.storage,
+		&new_ban.network,
+		new_ban.reason.as_deref(),
+		new_ban.expires_in_days,
+	)
+	.await?;
+	Ok(warp::reply::with_status(warp::reply::json(&ban), StatusCode::CREATED).into_response())
+}
+
+/// Bans whoever uploaded a paste.
+pub async fn ban_paste(
+	id: String,
+	state: Arc<State>,
+	auth: Auth,
+	paste_ban: PasteBan,
+) -> Result<warp::reply::Response, Error> {
+	require_admin(&auth)?;
+	let id = i64::from(Id::decode(&state, &id).await?);
+	match bans::add_by_paste(
+		&*state.storage,
+		&state.config,
+		id,
+		paste_ban.reason.as_deref(),
+		paste_ban.expires_in_days,
+	)
+	.await?
+	{
+		Some(ban) => Ok(
+			warp::reply::with_status(warp::reply::json(&ban), StatusCode::CREATED).into_response(),
+		),
+		None => Ok(StatusCode::NOT_FOUND.into_response()),
+	}
+}
+
+/// Lifts a ban.
+pub async fn lift(id: i64, state: Arc<State>, auth: Auth) -> Result<warp::reply::Response, Error> {
+	require_admin(&auth)?;
+	if state.storage.lift_ban(id).await? {
+		Ok(StatusCode::NO_CONTENT.into_response())
+	} else {
+		Ok(StatusCode::NOT_FOUND.into_response())
+	}
+}
+
+/// Backs up the database, returning where the backup was written and how many old ones were deleted.
+pub async fn backup(state: Arc<State>, auth: Auth) -> Result<warp::reply::Response, Error> {
+	require_admin(&auth)?;
+	let (path, pruned) = backup::run(&*state.storage, &state.config.backups).await?;
+	Ok(warp::reply::json(&serde_json::json!({
+		"path": path.display().to_string(),
+		"pruned": pruned,
+	}))
+	.into_response())
+}
+
+/// What a moderator can do with a reported paste.
+pub enum Action {
+	/// Keeps the paste, throwing out its reports.
+	Dismiss,
+	/// Deletes the paste.
+	Delete,
+	/// Deletes the paste, and bans whoever uploaded it.
+	DeleteAndBan,
+}
+
+impl FromStr for Action {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"dismiss" => Ok(Self::Dismiss),
+			"delete" => Ok(Self::Delete),
+			"delete-and-ban" => Ok(Self::DeleteAndBan),
+			_ => Err(Error::IncompleteForm("action")),
+		}
+	}
+}
+
+/// Shows the moderation queue.
+pub async fn reports(state: Arc<State>, auth: Auth) -> Result<warp::reply::Response, Error> {
+	require_admin(&auth)?;
+	let queue = state.storage.reported().await?;
+	let mut listing = Vec::with_capacity(queue.len());
+	for paste in queue {
+		listing.push(serde_json::json!({
+			"id": Id::from(paste.id).encode(&state).await,
+			"name": paste.name,
+			"ip": paste.ip.map(|stored| privacy::display(&state.config, &stored)),
+			"hidden": paste.hidden,
+			"preview": paste.preview,
+			"reports": paste.reports,
+			"reasons": paste.reasons.lines().collect::<Vec<_>>(),
+		}));
+	}
+	let rendered = state
+		.handlebars
+		.render("reports", &serde_json::json!({ "pastes": listing }))?;
+	Ok(warp::reply::with_status(warp::reply::html(rendered), StatusCode::OK).into_response())
+}
+
+/// Acts on a reported paste, then goes back to the moderation queue.
+pub async fn moderate(
+	id: String,
+	action: Action,
+	state: Arc<State>,
+	ip: IpAddr,
+	auth: Auth,
+) -> Result<warp::reply::Response, Error> {
+	require_admin(&auth)?;
+	let paste_id = i64::from(Id::decode(&state, &id).await?);
+	match action {
+		Action::Dismiss => {
+			state.storage.dismiss(paste_id).await?;
+		}
+		Action::Delete => {
+			delete::remove(&state, &id, ip, &auth).await?;
+		}
+		Action::DeleteAndBan => {
+			// Ban them first, as the uploader's IP goes along with the paste.
+			bans::add_by_paste(
+				&*state.storage,
+				&state.config,
+				paste_id,
+				Some("reported paste"),
+				None,
+			)
+			.await?;
+			delete::remove(&state, &id, ip, &auth).await?;
+		}
+	}
+	Ok(see_other(
+		format!("{}/admin/reports", state.config.site_url),
+		None,
+	))
+}
@@ -0,0 +1,104 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{error::Error, id::Id, routes::get::not_found, state::State, storage::Search};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use warp::{http::StatusCode, Reply};
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Browse {
+	/// A full-text search over paste names and content.
+	#[serde(skip_serializing_if = "String::is_empty")]
+	q: String,
+	/// Only show pastes whose name contains this.
+	#[serde(skip_serializing_if = "String::is_empty")]
+	name: String,
+	/// Only show pastes in this language.
+	#[serde(skip_serializing_if = "String::is_empty")]
+	language: String,
+	/// Which page to show, starting from 1.
+	#[serde(skip_serializing_if = "is_first_page")]
+	page: u32,
+}
+
+fn is_first_page(page: &u32) -> bool {
+	*page <= 1
+}
+
+/// The link to a page of the same search.
+fn page_url(browse: &Browse, page: u32) -> String {
+	let query = serde_urlencoded::to_string(Browse {
+		page,
+		..browse.clone()
+	})
+	.unwrap_or_default();
+	if query.is_empty() {
+		"/browse".to_string()
+	} else {
+		format!("/browse?{}", query)
+	}
+}
+
+/// Lists public pastes, newest first.
+pub async fn browse(state: Arc<State>, browse: Browse) -> Result<warp::reply::Response, Error> {
+	if !state.config.browse.enabled {
+		return Ok(not_found(&state)?.into_response());
+	}
+	let page = browse.page.max(1);
+	let page_size = state.config.browse.page_size.get();
+	let offset = i64::from(page - 1) * i64::from(page_size);
+	// Fetch one more than we need, to see if there's another page.
+	let limit = i64::from(page_size) + 1;
+	let mut pastes = state
+		.storage
+		.browse(Search {
+			words: Some(browse.q.trim()).filter(|words| !words.is_empty()),
+			name: Some(browse.name.trim()).filter(|name| !name.is_empty()),
+			language: Some(browse.language.as_str()).filter(|language| !language.is_empty()),
+			limit,
+			offset,
+		})
+		.await?;
+	let more = pastes.len() > page_size as usize;
+	pastes.truncate(page_size as usize);
+	let mut listing = Vec::with_capacity(pastes.len());
+	for paste in pastes {
+		listing.push(serde_json::json!({
+			"id": Id::from(paste.id).encode(&state).await,
+			"name": paste.name,
+			"language": paste.syntax.unwrap_or_else(|| "Plain Text".to_string()),
+			"posted": paste.posted,
+			"size": paste.size,
+		}));
+	}
+	let languages = state
+		.languages()
+		.into_iter()
+		.map(|language| {
+			serde_json::json!({
+				"selected": language == browse.language,
+				"name": language,
+			})
+		})
+		.collect::<Vec<_>>();
+	let rendered = state.handlebars.render(
+		"browse",
+		&serde_json::json!({
+			"pastes": listing,
+			"q": browse.q,
+			"name": browse.name,
+			"languages": languages,
+			"page": page,
+			"previous": if page > 1 { Some(page_url(&browse, page - 1)) } else { None },
+			"next": if more { Some(page_url(&browse, page + 1)) } else { None },
+		}),
+	)?;
+	Ok(warp::reply::with_status(warp::reply::html(rendered), StatusCode::OK).into_response())
+}
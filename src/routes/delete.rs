@@ -6,47 +6,69 @@
 	file, You can obtain one at http://mozilla.org/MPL/2.0/.
 */
 
-use crate::{error::Error, id::Id, state::State};
+use crate::{
+	auth::{Auth, Scope},
+	config::IpStorage,
+	error::Error,
+	id::Id,
+	privacy,
+	state::State,
+	storage::Deleter,
+};
 use std::{net::IpAddr, sync::Arc};
 use warp::http::StatusCode;
 
+/// Deletes a paste, if the sender is allowed to, returning whether it was deleted.
+pub(crate) async fn remove(
+	state: &State,
+	id: &str,
+	ip: IpAddr,
+	auth: &Auth,
+) -> Result<bool, Error> {
+	let id = i64::from(Id::decode(state, id).await?);
+	// Convert the IP address to bytes, in the same form as it's stored.
+	// Hashes are the same every time, so they still match the uploader's,
+	// but a truncated IP is shared by its whole network,
+	// which would let neighbours delete each other's pastes.
+	let ip_bytes = match state.config.privacy.ip_storage {
+		IpStorage::Raw | IpStorage::Hashed => Some(privacy::store(&state.config, ip)),
+		IpStorage::Truncated => None,
+	};
+	// Check to see if we're allowed to delete any paste.
+	let delete_any = auth.can(Scope::DeleteAny);
+	// Or just the pastes uploaded with this API key, or by this user.
+	let delete_own = auth.can(Scope::DeleteOwn);
+	let own_key = auth.key_id.filter(|_| delete_own);
+	let own_user = auth
+		.user
+		.as_ref()
+		.map(|user| user.id)
+		.filter(|_| delete_own);
+	// Delete the paste from storage,
+	// as long as the sender's IP address matches that of the uploader's,
+	// or it was uploaded with the sender's API key or account,
+	// or if `delete_any` is true.
+	let deleter = Deleter {
+		ip: ip_bytes.as_deref(),
+		any: delete_any,
+		key_id: own_key,
+		owner_id: own_user,
+	};
+	if state.storage.delete(id, deleter).await? {
+		state.cache.lock().await.pop(&id);
+		Ok(true)
+	} else {
+		Ok(false)
+	}
+}
+
 pub async fn delete(
 	id: String,
 	state: Arc<State>,
 	ip: IpAddr,
-	authorization: Option<String>,
+	auth: Auth,
 ) -> Result<impl warp::Reply, Error> {
-	let id = i64::from(Id::decode(&state, &id).await?);
-	// Convert the IP address to bytes.
-	let ip_bytes = match ip {
-		IpAddr::V4(ipv4) => ipv4.octets().to_vec(),
-		IpAddr::V6(ipv6) => ipv6.octets().to_vec(),
-	};
-	// Check to see if the API key matches.
-	let authorized = authorization
-		.map(|key| key == state.config.password)
-		.unwrap_or(false);
-	// Delete the paste from the database,
-	// as long as the sender's IP address matches that of the uploader's,
-	// or if `authorized` is true.
-	if sqlx::query!(
-		r#"
-		DELETE FROM
-			pastes
-		WHERE
-			id = $1 AND
-			(ip = $2 OR $3 = 1)
-	"#,
-		id,
-		ip_bytes,
-		authorized
-	)
-	.execute(&state.pool)
-	.await?
-	.rows_affected()
-		> 0
-	{
-		state.cache.lock().await.pop(&id);
+	if remove(&state, &id, ip, &auth).await? {
 		Ok(warp::reply::with_status("removed", StatusCode::OK))
 	} else {
 		Ok(warp::reply::with_status(
@@ -55,3 +77,30 @@ pub async fn delete(
 		))
 	}
 }
+
+/// Deletes a paste from a form, for browsers that can't send `DELETE` requests.
+/// Logged-in users are sent back to their paste list afterwards.
+pub async fn delete_form(
+	id: String,
+	state: Arc<State>,
+	ip: IpAddr,
+	auth: Auth,
+) -> Result<impl warp::Reply, Error> {
+	let removed = remove(&state, &id, ip, &auth).await?;
+	let location = match &auth.user {
+		Some(user) => format!("{}/~{}", state.config.site_url, user.username),
+		None => state.config.site_url.clone(),
+	};
+	Ok(warp::reply::with_header(
+		warp::reply::with_status(
+			if removed { "removed" } else { "not removed" },
+			if removed {
+				StatusCode::SEE_OTHER
+			} else {
+				StatusCode::BAD_REQUEST
+			},
+		),
+		"Location",
+		location,
+	))
+}
@@ -0,0 +1,238 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{error::Error, id::Id, routes::get::not_found, state::State};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{sync::Arc, time::SystemTime};
+use warp::{
+	http::{header, Response, StatusCode},
+	hyper::Body,
+	Reply,
+};
+
+/// The kinds of feed we can serve.
+#[derive(Clone, Copy)]
+pub enum Format {
+	Atom,
+	Rss,
+}
+
+impl Format {
+	/// The path this feed is served at.
+	fn path(self) -> &'static str {
+		match self {
+			Self::Atom => "/feed.atom",
+			Self::Rss => "/feed.rss",
+		}
+	}
+
+	/// The `Content-Type` this feed is served with.
+	fn content_type(self) -> &'static str {
+		match self {
+			Self::Atom => "application/atom+xml; charset=utf-8",
+			Self::Rss => "application/rss+xml; charset=utf-8",
+		}
+	}
+
+	/// Formats a date the way this feed wants it.
+	fn date(self, date: DateTime<Utc>) -> String {
+		match self {
+			Self::Atom => date.to_rfc3339_opts(SecondsFormat::Secs, true),
+			Self::Rss => httpdate::fmt_http_date(date.into()),
+		}
+	}
+}
+
+/// Parses a timestamp from SQLite, like `2021-08-18 21:36:25`.
+fn parse_posted(posted: &str) -> Option<DateTime<Utc>> {
+	NaiveDateTime::parse_from_str(posted, "%Y-%m-%d %H:%M:%S")
+		.ok()
+		.map(|date| DateTime::from_utc(date, Utc))
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Feed {
+	/// Only include pastes in this language.
+	#[serde(skip_serializing_if = "String::is_empty")]
+	language: String,
+	/// Only include pastes owned by this user.
+	#[serde(skip_serializing_if = "String::is_empty")]
+	user: String,
+}
+
+/// Whether an `If-None-Match` header, which may list several ETags, matches ours.
+fn etag_matches(tags: &str, etag: &str) -> bool {
+	tags.split(',').map(str::trim).any(|tag| {
+		// Weak ETags still match, as we only compare them to see if anything changed.
+		tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag
+	})
+}
+
+/// Strips out control characters, which aren't allowed in XML.
+fn xml_safe(s: &str) -> String {
+	s.chars()
+		.filter(|c| matches!(c, '\t' | '\n' | '\r') || !c.is_control())
+		.collect()
+}
+
+/// Lists the newest public pastes, as an Atom or RSS feed.
+pub async fn feed(
+	format: Format,
+	state: Arc<State>,
+	feed: Feed,
+	if_none_match: Option<String>,
+	if_modified_since: Option<String>,
+) -> Result<warp::reply::Response, Error> {
+	if !state.config.feeds.enabled {
+		return Ok(not_found(&state)?.into_response());
+	}
+	let language = Some(feed.language.as_str()).filter(|language| !language.is_empty());
+	let user = Some(feed.user.as_str()).filter(|user| !user.is_empty());
+	let limit = i64::from(state.config.feeds.entries.get());
+	let excerpt_length = i64::from(state.config.feeds.excerpt_length);
+	// Password-protected pastes are listed, but never excerpted.
+	let pastes = state
+		.storage
+		.feed(language, user, limit, excerpt_length)
+		.await?;
+	// The feed changes whenever a paste is added to it or taken off it,
+	// so its ETag comes from the pastes it lists, and how long their excerpts are.
+	let mut hasher = Sha256::new();
+	hasher.update(excerpt_length.to_be_bytes());
+	for paste in &pastes {
+		hasher.update(paste.id.to_be_bytes());
+	}
+	let etag = format!("\"{}\"", hex::encode(&hasher.finalize()[..16]));
+	// The feed was last updated when its newest paste was posted.
+	let updated = pastes
+		.iter()
+		.filter_map(|paste| parse_posted(&paste.posted))
+		.max();
+	let mut response = Response::builder().header(header::ETAG, etag.as_str());
+	if let Some(updated) = updated {
+		response = response.header(
+			header::LAST_MODIFIED,
+			httpdate::fmt_http_date(updated.into()),
+		);
+	}
+	// The ETag notices removals, which the modification time can't,
+	// so the modification time is only used by clients that don't send it back.
+	let not_modified = match (if_none_match.as_deref(), updated) {
+		(Some(tags), _) => etag_matches(tags, &etag),
+		(None, Some(updated)) => if_modified_since
+			.as_deref()
+			.and_then(|since| httpdate::parse_http_date(since).ok())
+			.is_some_and(|since| SystemTime::from(updated) <= since),
+		(None, None) => false,
+	};
+	if not_modified {
+		return Ok(response
+			.status(StatusCode::NOT_MODIFIED)
+			.body(Body::empty())
+			.unwrap_or_else(|_| unreachable!()));
+	}
+	let mut entries = Vec::with_capacity(pastes.len());
+	for paste in pastes {
+		let excerpt = if paste.locked {
+			None
+		} else {
+			let mut excerpt = xml_safe(&paste.excerpt);
+			if excerpt.chars().count() as i64 > excerpt_length {
+				excerpt = excerpt.chars().take(excerpt_length as usize).collect();
+				excerpt.push('…');
+			}
+			Some(excerpt)
+		};
+		entries.push(serde_json::json!({
+			"name": paste.name.as_deref().map(xml_safe),
+			"link": format!("{}/{}", state.config.site_url, Id::from(paste.id).encode(&state).await),
+			"updated": parse_posted(&paste.posted).map(|date| format.date(date)),
+			"author": paste.username,
+			"language": paste.syntax.unwrap_or_else(|| "Plain Text".to_string()),
+			"excerpt": excerpt.filter(|excerpt| !excerpt.trim().is_empty()),
+		}));
+	}
+	let mut title = "sweetpaste".to_string();
+	if let Some(language) = language {
+		title.push_str(&format!(" - {}", language));
+	}
+	if let Some(user) = user {
+		title.push_str(&format!(" - ~{}", user));
+	}
+	let query = serde_urlencoded::to_string(&feed).unwrap_or_default();
+	let this = if query.is_empty() {
+		format!("{}{}", state.config.site_url, format.path())
+	} else {
+		format!("{}{}?{}", state.config.site_url, format.path(), query)
+	};
+	let rendered = state.handlebars.render(
+		match format {
+			Format::Atom => "atom",
+			Format::Rss => "rss",
+		},
+		&serde_json::json!({
+			"title": xml_safe(&title),
+			"self": this,
+			"site": state.config.site_url,
+			// Atom feeds always need an update time, even when empty.
+			"updated": match (format, updated) {
+				(_, Some(date)) => Some(format.date(date)),
+				(Format::Atom, None) => Some("1970-01-01T00:00:00Z".to_string()),
+				(Format::Rss, None) => None,
+			},
+			"entries": entries,
+		}),
+	)?;
+	Ok(response
+		.header(header::CONTENT_TYPE, format.content_type())
+		.body(Body::from(rendered))
+		.unwrap_or_else(|_| unreachable!()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	#[test]
+	fn posted() {
+		assert_eq!(
+			parse_posted("2021-03-04 05:06:07"),
+			Some(Utc.ymd(2021, 3, 4).and_hms(5, 6, 7))
+		);
+		for invalid in [
+			"",
+			"2021-03-04",
+			"2021-03-04T05:06:07Z",
+			"2021-02-30 00:00:00",
+		] {
+			assert_eq!(parse_posted(invalid), None, "{}", invalid);
+		}
+	}
+
+	#[test]
+	fn etags() {
+		let etag = "\"0123abcd\"";
+		assert!(etag_matches("\"0123abcd\"", etag));
+		assert!(etag_matches("W/\"0123abcd\"", etag));
+		assert!(etag_matches("\"ffff\", W/\"0123abcd\"", etag));
+		assert!(etag_matches("\"ffff\",\"0123abcd\"", etag));
+		assert!(etag_matches("*", etag));
+		assert!(!etag_matches("\"ffff\"", etag));
+		assert!(!etag_matches("0123abcd", etag));
+		assert!(!etag_matches("", etag));
+	}
+
+	#[test]
+	fn control_characters() {
+		assert_eq!(xml_safe("a\tb\nc\r\u{0}d\u{1b}[0m"), "a\tb\nc\rd[0m");
+	}
+}
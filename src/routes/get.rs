@@ -6,86 +6,130 @@
 	file, You can obtain one at http://mozilla.org/MPL/2.0/.
 */
 
-use crate::{error::Error, id::Id, state::State};
-use std::{collections::BTreeMap, sync::Arc};
-use syntect::parsing::SyntaxReference;
+use crate::{
+	auth::{Auth, Scope},
+	error::Error,
+	id::Id,
+	state::State,
+	visibility::Visibility,
+};
+use std::{collections::BTreeMap, sync::Arc, time::Instant};
 
-fn render(
+/// Renders a paste into its HTML page.
+pub(crate) fn render(
 	state: &State,
-	syntax: &SyntaxReference,
+	id: &str,
 	name: Option<String>,
+	syntax: Option<&str>,
 	_posted: i64,
 	content: &str,
 ) -> Result<String, Error> {
+	// Find the syntax highlighter for this paste,
+	// otherwise use plain text as a fallback.
+	let syntax = syntax
+		.and_then(|syntax_name| state.syntax_set.find_syntax_by_name(syntax_name))
+		.unwrap_or_else(|| state.syntax_set.find_syntax_plain_text());
+	let started = Instant::now();
 	let highlighted = syntect::html::highlighted_html_for_string(
 		content,
 		&state.syntax_set,
 		syntax,
 		&state.theme_set.themes[state.config.syntax_highlighting.theme.as_str()],
 	);
+	state.metrics.render(&syntax.name, started.elapsed());
 	let mut data = BTreeMap::<&'static str, String>::new();
 	data.insert("content", highlighted);
 	if let Some(name) = name {
 		data.insert("name", name);
 	}
 	data.insert("language", syntax.name.clone());
+	data.insert("id", id.to_string());
+	if state.config.reports.enabled {
+		data.insert("reports", "true".to_string());
+	}
 	state.handlebars.render("paste", &data).map_err(Error::from)
 }
 
-pub async fn get(id: String, state: Arc<State>) -> Result<impl warp::Reply, Error> {
-	let id = i64::from(Id::decode(&state, &id).await?);
+/// Renders the 404 page.
+pub(crate) fn not_found(
+	state: &State,
+) -> Result<warp::reply::WithStatus<warp::reply::Html<String>>, Error> {
+	let rendered = state.handlebars.render("404", &())?;
+	Ok(warp::reply::with_status(
+		warp::reply::html(rendered),
+		warp::http::StatusCode::NOT_FOUND,
+	))
+}
+
+pub async fn get(
+	encoded_id: String,
+	state: Arc<State>,
+	auth: Auth,
+) -> Result<impl warp::Reply, Error> {
+	// Anything that isn't a paste ID gets the 404 page, like a paste that doesn't exist.
+	let id = match Id::decode(&state, &encoded_id).await {
+		Ok(id) => i64::from(id),
+		Err(Error::Mnemonic(_) | Error::InvalidId) => return not_found(&state),
+		Err(err) => return Err(err),
+	};
 	let mut cache = state.cache.lock().await;
 	// Check the cache for the rendered HTML for this paste, and if so, just return that.
-	if let Some(response) = cache.get(&id) {
-		return Ok(warp::reply::with_status(
-			warp::reply::html(response.clone()),
-			warp::http::StatusCode::OK,
-		));
+	// Private pastes never make it into the cache, so this is safe for anyone to see.
+	if let Some(response) = cache.get(&id).cloned() {
+		// Other servers sharing the database can't empty our cache,
+		// so make sure they haven't deleted, hidden or made the paste private since it was cached.
+		if state.config.database_url.is_none() || state.storage.cacheable(id).await? {
+			state.cache_stats.hit();
+			return Ok(warp::reply::with_status(
+				warp::reply::html(response),
+				warp::http::StatusCode::OK,
+			));
+		}
+		cache.pop(&id);
 	}
+	state.cache_stats.miss();
 	// Try to find the paste with the given ID.
-	let paste = match sqlx::query!(
-		r#"
-		SELECT
-			name, syntax, content, posted as "posted: i64"
-		FROM
-			pastes
-		WHERE
-			id = $1
-		"#,
-		id
-	)
-	.fetch_optional(&state.pool)
-	.await?
-	{
+	let paste = match state.storage.find(id).await? {
 		// We found it!
 		Some(x) => x,
 		// We didn't find it? Time to render the 404 page.
-		None => {
-			let rendered = state.handlebars.render("404", &()).map_err(Error::from)?;
-			return Ok(warp::reply::with_status(
-				warp::reply::html(rendered),
-				warp::http::StatusCode::NOT_FOUND,
-			));
-		}
+		None => return not_found(&state),
 	};
-	// Find the syntax highlighter for this paste,
-	// otherwise use plain text as a fallback.
-	let syntax_highlighting = paste
-		.syntax
-		.as_ref()
-		.and_then(|syntax_name| state.syntax_set.find_syntax_by_name(syntax_name))
-		.unwrap_or_else(|| state.syntax_set.find_syntax_plain_text());
+	// Private pastes pretend not to exist for anyone who can't see them.
+	let private = Visibility::from_db(&paste.visibility) == Visibility::Private;
+	if private && !auth.can_view_private(paste.owner_id, paste.key_id) {
+		return not_found(&state);
+	}
+	// Pastes hidden by reports are only visible to admins, until they're reviewed.
+	if paste.hidden && !auth.can(Scope::Admin) {
+		return not_found(&state);
+	}
+	// Password-protected pastes get a password form instead,
+	// and are never cached, as the cache doesn't know who's unlocked what.
+	if paste.password.is_some() {
+		let rendered = state
+			.handlebars
+			.render("unlock", &serde_json::json!({ "incorrect": false }))?;
+		return Ok(warp::reply::with_status(
+			warp::reply::html(rendered),
+			warp::http::StatusCode::OK,
+		));
+	}
 
 	// Render the paste.
 	let rendered = render(
 		&state,
-		syntax_highlighting,
+		&encoded_id,
 		paste.name,
+		paste.syntax.as_deref(),
 		paste.posted,
 		&paste.content,
 	)?;
 	// Cache the rendered HTML for this paste, and return it.
-	let _ = cache.put_with_weight(id, rendered.clone());
+	// Private and hidden pastes are never cached, so they can't be served to the wrong person.
+	if !private && !paste.hidden {
+		crate::cache::insert(&mut cache, &state.cache_stats, id, rendered.clone());
+	}
 	Ok(warp::reply::with_status(
 		warp::reply::html(rendered),
 		warp::http::StatusCode::OK,
@@ -0,0 +1,71 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{error::Error, routes::get::not_found, state::State};
+use std::sync::Arc;
+use warp::{http::StatusCode, Reply};
+
+/// Templates that no page can be served without.
+const REQUIRED_TEMPLATES: &[&str] = &["404", "paste", "upload"];
+
+/// Serves the liveness check on the main address.
+/// If there's an admin address, it's only served there.
+pub async fn healthz(state: Arc<State>) -> Result<warp::reply::Response, Error> {
+	if state.config.admin_address.is_some() {
+		return Ok(not_found(&state)?.into_response());
+	}
+	Ok(alive())
+}
+
+/// Serves the readiness check on the main address.
+/// If there's an admin address, it's only served there.
+pub async fn readyz(state: Arc<State>) -> Result<warp::reply::Response, Error> {
+	if state.config.admin_address.is_some() {
+		return Ok(not_found(&state)?.into_response());
+	}
+	Ok(ready(state).await)
+}
+
+/// Says that we're alive, which we must be, if we're answering.
+pub fn alive() -> warp::reply::Response {
+	warp::reply::json(&serde_json::json!({ "status": "ok" })).into_response()
+}
+
+/// Checks whether we're ready to serve pastes, answering with 503 if we aren't.
+pub async fn ready(state: Arc<State>) -> warp::reply::Response {
+	// Make sure the database is actually answering.
+	let database = state.storage.ping().await.is_ok();
+	// Make sure every migration we know of has been applied.
+	let migrations = state.storage.migrated().await;
+	let syntaxes = !state.syntax_set.syntaxes().is_empty()
+		&& state
+			.theme_set
+			.themes
+			.contains_key(&state.config.syntax_highlighting.theme);
+	let templates = REQUIRED_TEMPLATES
+		.iter()
+		.all(|name| state.handlebars.has_template(name));
+	let ready = database && migrations && syntaxes && templates;
+	warp::reply::with_status(
+		warp::reply::json(&serde_json::json!({
+			"status": if ready { "ready" } else { "unavailable" },
+			"checks": {
+				"database": database,
+				"migrations": migrations,
+				"syntaxes": syntaxes,
+				"templates": templates,
+			},
+		})),
+		if ready {
+			StatusCode::OK
+		} else {
+			StatusCode::SERVICE_UNAVAILABLE
+		},
+	)
+	.into_response()
+}
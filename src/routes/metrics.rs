@@ -0,0 +1,40 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{
+	auth::Auth,
+	error::Error,
+	routes::{admin::require_admin, get::not_found},
+	state::State,
+};
+use std::sync::Arc;
+use warp::{http::header, Reply};
+
+/// Serves metrics on the main address, to admins.
+/// If there's an admin address, they're only served there.
+pub async fn metrics(state: Arc<State>, auth: Auth) -> Result<warp::reply::Response, Error> {
+	if state.config.admin_address.is_some() {
+		return Ok(not_found(&state)?.into_response());
+	}
+	require_admin(&auth)?;
+	scrape(state).await
+}
+
+/// Serves metrics in the Prometheus text format.
+pub async fn scrape(state: Arc<State>) -> Result<warp::reply::Response, Error> {
+	if !state.config.metrics.enabled {
+		return Ok(not_found(&state)?.into_response());
+	}
+	let metrics = state.metrics.export(&state).await;
+	Ok(warp::reply::with_header(
+		metrics,
+		header::CONTENT_TYPE,
+		"text/plain; version=0.0.4; charset=utf-8",
+	)
+	.into_response())
+}
@@ -6,6 +6,15 @@
 	file, You can obtain one at http://mozilla.org/MPL/2.0/.
 */
 
+pub(crate) mod account;
+pub(crate) mod admin;
+pub(crate) mod browse;
 pub(crate) mod delete;
+pub(crate) mod feed;
 pub(crate) mod get;
+pub(crate) mod health;
+pub(crate) mod metrics;
 pub(crate) mod post;
+pub(crate) mod report;
+pub(crate) mod stats;
+pub(crate) mod unlock;
@@ -6,10 +6,22 @@
 	file, You can obtain one at http://mozilla.org/MPL/2.0/.
 */
 
-use crate::{error::Error, id::Id, state::State};
+use crate::{
+	auth::{self, Auth, Scope},
+	error::Error,
+	id::Id,
+	password, privacy,
+	quota::{Quota, Remaining},
+	state::State,
+	storage::NewPaste,
+	visibility::Visibility,
+};
 use serde::Deserialize;
 use std::{net::IpAddr, sync::Arc};
-use warp::http::StatusCode;
+use warp::{
+	http::{HeaderValue, StatusCode},
+	Reply,
+};
 
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -17,20 +29,25 @@ pub struct Upload {
 	password: Option<String>,
 	name: Option<String>,
 	syntax: Option<String>,
+	paste_password: Option<String>,
+	#[serde(default)]
+	visibility: Visibility,
 	content: String,
 }
 
 pub async fn post(
 	state: Arc<State>,
 	ip: IpAddr,
-	authorization: Option<String>,
+	auth: Auth,
 	upload: Upload,
-) -> Result<impl warp::Reply, Error> {
-	let authorized = authorization
-		.or_else(|| upload.password.clone())
-		.map(|auth| auth == state.config.password)
-		.unwrap_or(false);
-	if !state.config.public && !authorized {
+) -> Result<warp::reply::Response, Error> {
+	// Fall back to the password in the form, if the header didn't authorize anything.
+	// Empty form fields count as not having one.
+	let auth = match upload.password.as_deref().filter(|pass| !pass.is_empty()) {
+		Some(password) if !auth.is_authorized() => auth::authorize(&state, ip, password).await?,
+		_ => auth,
+	};
+	if !state.config.public && !auth.can(Scope::Upload) {
 		return Ok(warp::reply::with_header(
 			warp::reply::with_status(
 				warp::reply::html("unauthorized".to_string()),
@@ -38,49 +55,94 @@ pub async fn post(
 			),
 			"Location",
 			state.config.site_url.clone(),
-		));
+		)
+		.into_response());
 	}
+	// Run the paste through the content filter, which may reject or change it.
+	let mut content = upload.content;
+	let warnings = state
+		.content_filter
+		.check(&mut content, auth.is_trusted())?;
+	// Work out how much the uploader may upload, which is checked as the paste is stored.
+	let quota = Quota::new(&state, ip, &auth);
+	let size = content.len() as i64;
 	// Convert the IP address to bytes.
-	let ip_bytes = match ip {
-		IpAddr::V4(ipv4) => ipv4.octets().to_vec(),
-		IpAddr::V6(ipv6) => ipv6.octets().to_vec(),
-	};
+	let ip_bytes = privacy::store(&state.config, ip);
 	// Get the syntax name, if any.
 	// We'll also check the first line of the content.
 	let syntax = upload
 		.syntax
 		.as_ref()
 		.and_then(|syntax_name| state.syntax_set.find_syntax_by_token(syntax_name))
-		.or_else(|| state.syntax_set.find_syntax_by_first_line(&upload.content))
+		.or_else(|| state.syntax_set.find_syntax_by_first_line(&content))
 		.map(|syntax| syntax.name.to_string());
-	// Submit the paste to the database, getting the new ID in return.
-	let id = Id::from(
-		sqlx::query!(
-			r#"
-		INSERT INTO pastes
-			(name, ip, syntax, content)
-		VALUES
-			($1, $2, $3, $4)
-		RETURNING
-			id as "id: i64"
-		"#,
-			upload.name,
-			ip_bytes,
-			syntax,
-			upload.content
+	// Pastes uploaded while logged in belong to that user.
+	let owner_id = auth.user.as_ref().map(|user| user.id);
+	// Private pastes need an uploader who'll be able to see them afterwards.
+	if upload.visibility == Visibility::Private && !auth.can_view_private(owner_id, auth.key_id) {
+		return Err(Error::PrivateAnonymous);
+	}
+	let visibility = upload.visibility.as_str();
+	// Hash the paste's password, if it has one.
+	// Empty form fields count as not having one.
+	let paste_password = match upload.paste_password.filter(|pass| !pass.is_empty()) {
+		Some(pass) => Some(password::hash(pass).await?),
+		None => None,
+	};
+	// Submit the paste to storage, getting the new ID in return.
+	let (id, usage) = state
+		.storage
+		.insert(
+			NewPaste {
+				name: upload.name.as_deref(),
+				ip: &ip_bytes,
+				syntax: syntax.as_deref(),
+				content: &content,
+				password: paste_password.as_deref(),
+				key_id: auth.key_id,
+				owner_id,
+				visibility,
+				size,
+			},
+			quota.as_ref(),
 		)
-		.fetch_one(&state.pool)
-		.await?
-		.id,
-	);
+		.await?;
+	let id = Id::from(id);
+	state.metrics.upload(size as usize);
 	let url = format!("{}/{}", state.config.site_url, id.encode(&state).await);
 	let response = state
 		.handlebars
 		.render("redirect", &serde_json::json!({ "url": url }))?;
 	// Reply with the URL to the new paste, along with a redirect header.
-	Ok(warp::reply::with_header(
+	let mut reply = warp::reply::with_header(
 		warp::reply::with_status(warp::reply::html(response), warp::http::StatusCode::CREATED),
 		"Location",
 		url,
-	))
+	)
+	.into_response();
+	// Let the uploader know how much of their quota is left.
+	let remaining = match (&quota, &usage) {
+		(Some(quota), Some(usage)) => quota.remaining(usage),
+		_ => Remaining {
+			pastes: None,
+			bytes: None,
+		},
+	};
+	if let Some(pastes) = remaining.pastes {
+		reply
+			.headers_mut()
+			.insert("X-Quota-Remaining-Pastes", HeaderValue::from(pastes));
+	}
+	if let Some(bytes) = remaining.bytes {
+		reply
+			.headers_mut()
+			.insert("X-Quota-Remaining-Bytes", HeaderValue::from(bytes));
+	}
+	// Let them know about anything the content filter noticed, too.
+	if !warnings.is_empty() {
+		if let Ok(warnings) = HeaderValue::from_str(&warnings.join("; ")) {
+			reply.headers_mut().insert("X-Content-Warning", warnings);
+		}
+	}
+	Ok(reply)
 }
@@ -0,0 +1,53 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{error::Error, id::Id, privacy, routes::get::not_found, state::State};
+use serde::Deserialize;
+use std::{net::IpAddr, sync::Arc};
+use warp::{http::StatusCode, Reply};
+
+#[derive(Deserialize)]
+pub struct Report {
+	reason: String,
+}
+
+/// Reports a paste for moderation.
+pub async fn report(
+	id: String,
+	state: Arc<State>,
+	ip: IpAddr,
+	report: Report,
+) -> Result<warp::reply::Response, Error> {
+	if !state.config.reports.enabled {
+		return Ok(not_found(&state)?.into_response());
+	}
+	let id = i64::from(Id::decode(&state, &id).await?);
+	let reason = report.reason.trim();
+	if reason.is_empty() {
+		return Err(Error::IncompleteForm("reason"));
+	}
+	let ip = privacy::store(&state.config, ip);
+	match state
+		.storage
+		.report(id, reason, &ip, state.config.reports.hide_after)
+		.await?
+	{
+		Some(hidden) => {
+			// Hidden pastes mustn't be served from the cache.
+			if hidden {
+				state.cache.lock().await.pop(&id);
+			}
+			Ok(warp::reply::with_status(
+				warp::reply::html("Thanks, the paste has been reported.".to_string()),
+				StatusCode::CREATED,
+			)
+			.into_response())
+		}
+		None => Ok(not_found(&state)?.into_response()),
+	}
+}
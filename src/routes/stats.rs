@@ -0,0 +1,69 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{error::Error, routes::get::not_found, state::State};
+use std::sync::Arc;
+use warp::{http::StatusCode, Reply};
+
+/// Gathers up every statistic, for either the page or the JSON endpoint.
+async fn gather(state: &State) -> Result<serde_json::Value, Error> {
+	let aggregates = state
+		.stats
+		.get(&*state.storage, &state.config.stats)
+		.await?;
+	let hits = state.cache_stats.hits();
+	let misses = state.cache_stats.misses();
+	let (entries, weight, capacity) = {
+		let cache = state.cache.lock().await;
+		(cache.len(), cache.weight(), cache.capacity())
+	};
+	Ok(serde_json::json!({
+		"total_pastes": aggregates.total_pastes,
+		"total_bytes": aggregates.total_bytes,
+		"per_day": aggregates.per_day,
+		"languages": aggregates.languages,
+		"cache": {
+			"hits": hits,
+			"misses": misses,
+			"hit_rate": if hits + misses > 0 {
+				hits as f64 / (hits + misses) as f64
+			} else {
+				0.0
+			},
+			"entries": entries,
+			"bytes": weight,
+			"capacity": capacity,
+			"occupancy": weight as f64 / capacity as f64,
+		},
+	}))
+}
+
+/// Shows the statistics page.
+pub async fn stats(state: Arc<State>) -> Result<warp::reply::Response, Error> {
+	if !state.config.stats.enabled {
+		return Ok(not_found(&state)?.into_response());
+	}
+	let mut stats = gather(&state).await?;
+	// Percentages are easier to read than fractions.
+	let cache = &mut stats["cache"];
+	for key in ["hit_rate", "occupancy"] {
+		let percent = cache[key].as_f64().unwrap_or_default() * 100.0;
+		cache[key] = format!("{:.1}%", percent).into();
+	}
+	let rendered = state.handlebars.render("stats", &stats)?;
+	Ok(warp::reply::with_status(warp::reply::html(rendered), StatusCode::OK).into_response())
+}
+
+/// Returns the statistics as JSON.
+pub async fn stats_json(state: Arc<State>) -> Result<warp::reply::Response, Error> {
+	if !state.config.stats.enabled {
+		return Ok(not_found(&state)?.into_response());
+	}
+	let stats = gather(&state).await?;
+	Ok(warp::reply::json(&stats).into_response())
+}
@@ -0,0 +1,85 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{
+	auth::{Auth, Scope},
+	error::Error,
+	id::Id,
+	password,
+	ratelimit::Route,
+	routes::get::{not_found, render},
+	state::State,
+	visibility::Visibility,
+};
+use serde::Deserialize;
+use std::{net::IpAddr, sync::Arc};
+use warp::http::StatusCode;
+
+#[derive(Deserialize)]
+pub struct Unlock {
+	password: String,
+}
+
+pub async fn unlock(
+	encoded_id: String,
+	state: Arc<State>,
+	ip: IpAddr,
+	auth: Auth,
+	unlock: Unlock,
+) -> Result<impl warp::Reply, Error> {
+	let id = i64::from(Id::decode(&state, &encoded_id).await?);
+	// Bail out early if this IP has been guessing too much.
+	state.rate_limiter.check(Route::FailedAuth, ip).await?;
+	// Try to find the paste with the given ID.
+	let paste = match state.storage.find(id).await? {
+		Some(x) => x,
+		None => return not_found(&state),
+	};
+	// Private pastes pretend not to exist for anyone who can't see them.
+	if Visibility::from_db(&paste.visibility) == Visibility::Private
+		&& !auth.can_view_private(paste.owner_id, paste.key_id)
+	{
+		return not_found(&state);
+	}
+	// Pastes hidden by reports are only visible to admins, until they're reviewed.
+	if paste.hidden && !auth.can(Scope::Admin) {
+		return not_found(&state);
+	}
+	// If the paste has a password, check it.
+	// Pastes without one are just shown as-is.
+	if let Some(hash) = paste.password {
+		// Count this as a wrong guess up front, as checking the password is expensive,
+		// and concurrent guesses would all get past the limit before any of them were counted.
+		state.rate_limiter.take(Route::FailedAuth, ip).await?;
+		if password::verify(hash, unlock.password).await? {
+			// It wasn't a wrong guess after all.
+			state.rate_limiter.refund(Route::FailedAuth, ip).await;
+		} else {
+			let rendered = state
+				.handlebars
+				.render("unlock", &serde_json::json!({ "incorrect": true }))?;
+			return Ok(warp::reply::with_status(
+				warp::reply::html(rendered),
+				StatusCode::FORBIDDEN,
+			));
+		}
+	}
+	// Render the paste. This doesn't go in the cache!
+	let rendered = render(
+		&state,
+		&encoded_id,
+		paste.name,
+		paste.syntax.as_deref(),
+		paste.posted,
+		&paste.content,
+	)?;
+	Ok(warp::reply::with_status(
+		warp::reply::html(rendered),
+		StatusCode::OK,
+	))
+}
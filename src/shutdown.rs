@@ -0,0 +1,70 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use color_eyre::eyre::Result;
+use tokio::sync::watch;
+
+/// Tells the servers and background tasks when it's time to shut down.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+	/// Starts listening for SIGTERM and SIGINT, shutting down once either arrives.
+	pub fn listen() -> Result<Self> {
+		let (sender, receiver) = watch::channel(false);
+		let signals = signals()?;
+		tokio::spawn(async move {
+			let name = signals.await;
+			tracing::info!(signal = name, "shutting down");
+			let _ = sender.send(true);
+		});
+		Ok(Self(receiver))
+	}
+
+	/// Whether we've been told to shut down.
+	pub fn is_triggered(&self) -> bool {
+		*self.0.borrow()
+	}
+
+	/// Waits until it's time to shut down.
+	pub async fn wait(mut self) {
+		while !self.is_triggered() {
+			// If the sender's gone, nothing will ever tell us to shut down, but we'd better anyways.
+			if self.0.changed().await.is_err() {
+				return;
+			}
+		}
+	}
+}
+
+/// Sets up the signal handlers, giving a future that resolves to the name of the first signal caught.
+#[cfg(unix)]
+fn signals() -> Result<impl std::future::Future<Output = &'static str>> {
+	use color_eyre::eyre::WrapErr;
+	use tokio::signal::unix::{signal, SignalKind};
+	let mut terminate = signal(SignalKind::terminate()).wrap_err("failed to listen for SIGTERM")?;
+	let mut interrupt = signal(SignalKind::interrupt()).wrap_err("failed to listen for SIGINT")?;
+	Ok(async move {
+		tokio::select! {
+			_ = terminate.recv() => "SIGTERM",
+			_ = interrupt.recv() => "SIGINT",
+		}
+	})
+}
+
+/// Sets up the signal handlers, giving a future that resolves to the name of the first signal caught.
+#[cfg(not(unix))]
+fn signals() -> Result<impl std::future::Future<Output = &'static str>> {
+	Ok(async {
+		if let Err(err) = tokio::signal::ctrl_c().await {
+			tracing::error!(error = %err, "failed to listen for Ctrl-C");
+			std::future::pending::<()>().await;
+		}
+		"Ctrl-C"
+	})
+}
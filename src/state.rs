@@ -6,11 +6,19 @@
 	file, You can obtain one at http://mozilla.org/MPL/2.0/.
 */
 
-use crate::{cache::HtmlCache, config::Config};
+use crate::{
+	cache::{CacheStats, HtmlCache},
+	config::{Config, Source},
+	content_filter::ContentFilter,
+	metrics::Metrics,
+	ratelimit::RateLimiter,
+	stats::StatsCache,
+	storage::{self, Storage},
+};
 use chacha20::{cipher::NewCipher, ChaCha8, Key, Nonce};
-use color_eyre::eyre::{Result, WrapErr};
+use color_eyre::eyre::{eyre, Result, WrapErr};
 use handlebars::{Handlebars, Template};
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use std::sync::{Arc, RwLock};
 use syntect::{highlighting::ThemeSet, parsing::SyntaxSet};
 use tokio::sync::Mutex;
 
@@ -18,28 +26,84 @@ use tokio::sync::Mutex;
 pub struct State {
 	/// The application configuration.
 	pub config: Config,
-	/// The database connection pool.
-	pub pool: SqlitePool,
+	/// Where the configuration came from, so it can be reloaded from the same place.
+	source: Source,
+	/// The database, where pastes and everything else are kept.
+	pub storage: Arc<dyn Storage>,
 	/// The cache for rendered pages.
 	pub cache: HtmlCache,
+	/// How often the cache has been hit or missed.
+	pub cache_stats: Arc<CacheStats>,
 	/// The syntax set, which stores syntax highlighting stuff.
 	pub syntax_set: SyntaxSet,
 	/// The theme set, which stores syntax highlighting themes.
 	pub theme_set: ThemeSet,
 	/// The Handlebars context for templating.
 	pub handlebars: Handlebars<'static>,
+	/// The upload page, rendered ahead of time, as it only changes with the configuration.
+	pub upload_page: String,
 	/// The ChaCha8 context for encrypting paste IDs.
 	pub chacha: Mutex<ChaCha8>,
+	/// Per-IP rate limits.
+	pub rate_limiter: Arc<RateLimiter>,
+	/// The checks uploads go through before they're stored.
+	pub content_filter: ContentFilter,
+	/// The most recently computed instance statistics.
+	pub stats: StatsCache,
+	/// Metrics for Prometheus to scrape.
+	pub metrics: Arc<Metrics>,
 }
 
 impl State {
-	pub async fn new() -> Result<Self> {
+	pub async fn new(source: Source) -> Result<Self> {
 		// Load the configuration.
-		let config = Config::load().await.wrap_err("failed to load config")?;
+		let config = Config::load(&source)
+			.await
+			.wrap_err("failed to load config")?;
 		// Open the database.
-		let pool = Self::build_db(&config)
+		let storage = storage::open(&config)
 			.await
 			.wrap_err("failed to open database")?;
+		// Set up our rate limiter.
+		let rate_limiter = Arc::new(RateLimiter::new(&config.rate_limit));
+		Self::assemble(
+			config,
+			source,
+			storage,
+			rate_limiter,
+			Arc::default(),
+			Arc::default(),
+		)
+	}
+
+	/// Loads the configuration again, building a new state from it.
+	/// The database, rate limits and metrics carry over, but everything else is rebuilt, and the cache starts empty.
+	/// Settings that can't change without restarting keep their old values, and their names are returned.
+	pub async fn reload(&self) -> Result<(Self, Vec<&'static str>)> {
+		let mut config = Config::load(&self.source)
+			.await
+			.wrap_err("failed to load config")?;
+		let fixed = config.keep_fixed(&self.config);
+		let state = Self::assemble(
+			config,
+			self.source.clone(),
+			self.storage.clone(),
+			self.rate_limiter.clone(),
+			self.cache_stats.clone(),
+			self.metrics.clone(),
+		)?;
+		Ok((state, fixed))
+	}
+
+	/// Builds everything that comes from the configuration, around the parts that are kept between reloads.
+	fn assemble(
+		config: Config,
+		source: Source,
+		storage: Arc<dyn Storage>,
+		rate_limiter: Arc<RateLimiter>,
+		cache_stats: Arc<CacheStats>,
+		metrics: Arc<Metrics>,
+	) -> Result<Self> {
 		// Set up our in-memory cache.
 		let cache = crate::cache::create_cache(&config);
 		// Set up our syntax set.
@@ -54,32 +118,61 @@ impl State {
 			Key::from_slice(&config.id_key),
 			Nonce::from_slice(&[0_u8; 12]),
 		));
-		Ok(Self {
+		// Set up our content filter.
+		let content_filter = ContentFilter::new(&config.content_filter)
+			.wrap_err("failed to build content filter")?;
+		let mut state = Self {
 			config,
-			pool,
+			source,
+			storage,
 			cache,
+			cache_stats,
 			syntax_set,
 			theme_set,
 			handlebars,
+			upload_page: String::new(),
 			chacha,
-		})
+			rate_limiter,
+			content_filter,
+			stats: StatsCache::default(),
+			metrics,
+		};
+		// Pre-render the upload page, now that everything it needs is ready.
+		state.upload_page = state
+			.render_upload_page()
+			.wrap_err("failed to pre-render upload page")?;
+		Ok(state)
 	}
 
-	/// Open/create the SQLite database.
-	async fn build_db(config: &Config) -> Result<SqlitePool> {
-		let pool = SqlitePool::connect_with(
-			SqliteConnectOptions::new()
-				.filename(&config.db_path)
-				.create_if_missing(true),
-		)
-		.await
-		.wrap_err("failed to open sqlite db")?;
-		// Run SQLite migrations
-		sqlx::migrate!()
-			.run(&pool)
-			.await
-			.wrap_err("failed to run sqlite migrations")?;
-		Ok(pool)
+	/// The name of every syntax we have loaded, sorted.
+	pub fn languages(&self) -> Vec<String> {
+		let mut languages = self
+			.syntax_set
+			.syntaxes()
+			.iter()
+			.map(|syntax| syntax.name.clone())
+			.collect::<Vec<String>>();
+		languages.sort();
+		languages
+	}
+
+	/// Renders the upload page.
+	fn render_upload_page(&self) -> Result<String> {
+		// Get the name of every syntax we have loaded.
+		// We do this here for efficiency - we won't need to allocate
+		// this list every time we serve a paste.
+		let languages = self.languages();
+		let rendered = self.handlebars.render(
+			"upload",
+			&serde_json::json!({
+				"languages": &*languages,
+				"public": self.config.public,
+				"accounts": self.config.accounts.enabled,
+				"browse": self.config.browse.enabled,
+				"feeds": self.config.feeds.enabled,
+			}),
+		)?;
+		Ok(rendered)
 	}
 
 	/// Create the Handlebars state.
@@ -103,15 +196,70 @@ impl State {
 			Template::compile(include_str!("../template/upload.html"))
 				.wrap_err("failed to compile 'upload' template")?,
 		);
+		// Register the template for the password form page.
+		handlebars.register_template(
+			"unlock",
+			Template::compile(include_str!("../template/unlock.html"))
+				.wrap_err("failed to compile 'unlock' template")?,
+		);
+		// Register the template for the login/registration page.
+		handlebars.register_template(
+			"login",
+			Template::compile(include_str!("../template/login.html"))
+				.wrap_err("failed to compile 'login' template")?,
+		);
+		// Register the template for the user paste list page.
+		handlebars.register_template(
+			"user",
+			Template::compile(include_str!("../template/user.html"))
+				.wrap_err("failed to compile 'user' template")?,
+		);
 		// Register the template for the redirect page.
 		handlebars.register_template(
 			"redirect",
 			Template::compile(include_str!("../template/redirect.html"))
 				.wrap_err("failed to compile 'redirect' template")?,
 		);
+		// Register the template for the browse page.
+		handlebars.register_template(
+			"browse",
+			Template::compile(include_str!("../template/browse.html"))
+				.wrap_err("failed to compile 'browse' template")?,
+		);
+		// Register the templates for the Atom and RSS feeds.
+		handlebars.register_template(
+			"atom",
+			Template::compile(include_str!("../template/feed.atom"))
+				.wrap_err("failed to compile 'atom' template")?,
+		);
+		handlebars.register_template(
+			"rss",
+			Template::compile(include_str!("../template/feed.rss"))
+				.wrap_err("failed to compile 'rss' template")?,
+		);
+		// Register the template for the statistics page.
+		handlebars.register_template(
+			"stats",
+			Template::compile(include_str!("../template/stats.html"))
+				.wrap_err("failed to compile 'stats' template")?,
+		);
+		// Register the template for the moderation queue.
+		handlebars.register_template(
+			"reports",
+			Template::compile(include_str!("../template/reports.html"))
+				.wrap_err("failed to compile 'reports' template")?,
+		);
 		Ok(handlebars)
 	}
 
+	/// Makes sure everything that's built from the configuration builds, without touching the database.
+	pub fn check(config: &Config) -> Result<()> {
+		Self::build_syntax_definitions(config).wrap_err("failed to build syntax set")?;
+		Self::build_syntax_themes(config).wrap_err("failed to build theme set")?;
+		ContentFilter::new(&config.content_filter).wrap_err("failed to build content filter")?;
+		Ok(())
+	}
+
 	// Build the syntax definitions.
 	fn build_syntax_definitions(config: &Config) -> Result<SyntaxSet> {
 		let mut syntax_set_builder = SyntaxSet::load_defaults_newlines().into_builder();
@@ -131,6 +279,35 @@ impl State {
 				.add_from_folder(theme_path)
 				.wrap_err_with(|| format!("failed to add themes from {}", theme_path.display()))?;
 		}
+		// Pastes can't be highlighted without the configured theme.
+		let theme = &config.syntax_highlighting.theme;
+		if !theme_set.themes.contains_key(theme) {
+			return Err(eyre!("there's no theme named '{}'", theme));
+		}
 		Ok(theme_set)
 	}
 }
+
+/// The current state, which is swapped out wholesale whenever the configuration is reloaded.
+/// Requests keep whichever state they started with, so a reload never changes anything under them.
+pub struct SharedState(RwLock<Arc<State>>);
+
+impl SharedState {
+	pub fn new(state: State) -> Self {
+		Self(RwLock::new(Arc::new(state)))
+	}
+
+	/// The current state.
+	pub fn load(&self) -> Arc<State> {
+		// Swapping an `Arc` can't be left half-done, so a poisoned lock is fine to keep using.
+		self.0.read().unwrap_or_else(|err| err.into_inner()).clone()
+	}
+
+	/// Reloads the configuration, and swaps in the new state if it all loaded.
+	/// Returns the names of any changed settings that need a restart to take effect.
+	pub async fn reload(&self) -> Result<Vec<&'static str>> {
+		let (state, fixed) = self.load().reload().await?;
+		*self.0.write().unwrap_or_else(|err| err.into_inner()) = Arc::new(state);
+		Ok(fixed)
+	}
+}
@@ -0,0 +1,77 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use crate::{config::StatsConfig, error::Error, storage::Storage};
+use serde::Serialize;
+use std::{
+	sync::Arc,
+	time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+/// How many pastes were posted on a given day.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct Day {
+	/// The day, like `2021-08-18`.
+	pub day: String,
+	/// How many pastes were posted that day.
+	pub pastes: i64,
+}
+
+/// How many pastes are in a given language.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct Language {
+	/// The name of the language.
+	pub language: String,
+	/// How many pastes are in it.
+	pub pastes: i64,
+}
+
+/// Statistics that need the whole table to work out.
+#[derive(Serialize)]
+pub struct Aggregates {
+	/// How many pastes there are.
+	pub total_pastes: i64,
+	/// How many bytes of pastes there are.
+	pub total_bytes: i64,
+	/// How many pastes were posted on each recent day, oldest first.
+	/// Days without any pastes are left out.
+	pub per_day: Vec<Day>,
+	/// The most popular languages, most popular first.
+	pub languages: Vec<Language>,
+}
+
+/// Keeps the last computed [Aggregates] around,
+/// so that they aren't worked out again on every request.
+#[derive(Default)]
+pub struct StatsCache(Mutex<Option<(Instant, Arc<Aggregates>)>>);
+
+impl StatsCache {
+	/// Gets the statistics, working them out again if they're older than the refresh interval.
+	pub async fn get(
+		&self,
+		storage: &dyn Storage,
+		config: &StatsConfig,
+	) -> Result<Arc<Aggregates>, Error> {
+		// This stays locked while we work them out,
+		// so a burst of requests only hits the database once.
+		let mut cached = self.0.lock().await;
+		if let Some((computed, aggregates)) = cached.as_ref() {
+			if computed.elapsed() < Duration::from_secs(config.refresh_interval) {
+				return Ok(aggregates.clone());
+			}
+		}
+		let aggregates = Arc::new(
+			storage
+				.aggregates(config.days, config.top_languages)
+				.await?,
+		);
+		*cached = Some((Instant::now(), aggregates.clone()));
+		Ok(aggregates)
+	}
+}
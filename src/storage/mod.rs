@@ -0,0 +1,670 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+mod postgres;
+mod sqlite;
+
+use crate::{
+	account::User,
+	auth::ApiKey,
+	bans::Ban,
+	config::Config,
+	error::Error,
+	pastes::{self, Filter},
+	quota::Quota,
+	reports::Reported,
+	stats::Aggregates,
+};
+use async_trait::async_trait;
+use color_eyre::eyre::Result;
+use futures::stream::BoxStream;
+use std::{path::Path, sync::Arc};
+
+pub use self::{postgres::PostgresStorage, sqlite::SqliteStorage};
+
+/// A paste that's being uploaded.
+pub struct NewPaste<'a> {
+	pub name: Option<&'a str>,
+	/// The uploader's IP, already in its stored form.
+	pub ip: &'a [u8],
+	pub syntax: Option<&'a str>,
+	pub content: &'a str,
+	/// The hash of the paste's password, if it has one.
+	pub password: Option<&'a str>,
+	pub key_id: Option<i64>,
+	pub owner_id: Option<i64>,
+	pub visibility: &'static str,
+	pub size: i64,
+}
+
+/// A stored paste, along with everything needed to decide who may see it.
+#[derive(sqlx::FromRow)]
+pub struct Paste {
+	pub name: Option<String>,
+	pub syntax: Option<String>,
+	pub content: String,
+	pub posted: i64,
+	/// The hash of the paste's password, if it has one.
+	pub password: Option<String>,
+	pub visibility: String,
+	pub owner_id: Option<i64>,
+	pub key_id: Option<i64>,
+	pub hidden: bool,
+}
+
+/// Whoever's trying to delete a paste.
+/// They may delete it if they uploaded it from the same IP, with the same API key or account,
+/// or if they may delete any paste.
+pub struct Deleter<'a> {
+	/// Their IP, already in its stored form, if it's enough to go by.
+	pub ip: Option<&'a [u8]>,
+	pub any: bool,
+	pub key_id: Option<i64>,
+	pub owner_id: Option<i64>,
+}
+
+impl Deleter<'_> {
+	/// Someone who may delete any paste, like an admin at the command line.
+	pub fn anyone() -> Self {
+		Self {
+			ip: None,
+			any: true,
+			key_id: None,
+			owner_id: None,
+		}
+	}
+}
+
+/// How much has been uploaded from an IP range lately.
+#[derive(sqlx::FromRow)]
+pub struct Usage {
+	pub pastes: i64,
+	pub bytes: i64,
+}
+
+/// Narrows down which public pastes are browsed.
+pub struct Search<'a> {
+	/// Words to look for in paste names and content.
+	pub words: Option<&'a str>,
+	/// Only pastes whose name contains this.
+	pub name: Option<&'a str>,
+	/// Only pastes in this language.
+	pub language: Option<&'a str>,
+	pub limit: i64,
+	pub offset: i64,
+}
+
+/// A public paste, as it's listed when browsing.
+#[derive(sqlx::FromRow)]
+pub struct Listed {
+	pub id: i64,
+	pub name: Option<String>,
+	pub syntax: Option<String>,
+	pub posted: String,
+	pub size: i64,
+}
+
+/// A public paste, as it's listed in a feed.
+#[derive(sqlx::FromRow)]
+pub struct Entry {
+	pub id: i64,
+	pub name: Option<String>,
+	pub syntax: Option<String>,
+	pub posted: String,
+	pub locked: bool,
+	/// The start of the paste, one character longer than the excerpts shown, to see if they were cut off.
+	pub excerpt: String,
+	/// The name of the user who owns the paste.
+	pub username: Option<String>,
+}
+
+/// A paste, as it's listed on its owner's page.
+#[derive(sqlx::FromRow)]
+pub struct Owned {
+	pub id: i64,
+	pub name: Option<String>,
+	pub syntax: Option<String>,
+	pub posted: String,
+	pub visibility: String,
+}
+
+/// Everything about a paste, as it's exported and imported.
+#[derive(sqlx::FromRow)]
+pub struct Dump {
+	pub id: i64,
+	pub name: Option<String>,
+	pub syntax: Option<String>,
+	pub posted: String,
+	pub content: String,
+	/// The Argon2 hash of the paste's password.
+	pub password: Option<String>,
+	pub visibility: String,
+	pub hidden: bool,
+	/// The uploader's IP, in whatever form it was stored.
+	pub ip: Option<Vec<u8>>,
+	/// The name of the user who owns the paste.
+	pub owner: Option<String>,
+	/// The name of the API key the paste was uploaded with.
+	pub key: Option<String>,
+}
+
+/// Where everything's kept: pastes, reports, accounts, API keys and bans.
+/// Times come back in UTC, like `2021-08-18 21:36:25`, whichever database they're kept in.
+#[async_trait]
+pub trait Storage: Send + Sync {
+	/// Stores a new paste, returning its ID.
+	/// With a quota, the paste is only stored if it fits, returning [Error::QuotaExceeded] otherwise,
+	/// along with how much has been uploaded within the quota's window, this paste included.
+	/// Checking the quota and storing the paste happen atomically, so concurrent uploads can't all slip in.
+	async fn insert(
+		&self,
+		paste: NewPaste<'_>,
+		quota: Option<&Quota>,
+	) -> Result<(i64, Option<Usage>), Error>;
+
+	/// Finds the paste with the given ID.
+	async fn find(&self, id: i64) -> Result<Option<Paste>, Error>;
+
+	/// Whether a paste still exists, and can be shown to anyone without a password.
+	async fn cacheable(&self, id: i64) -> Result<bool, Error>;
+
+	/// Deletes a paste, if the deleter is allowed to, returning whether it was deleted.
+	async fn delete(&self, id: i64, deleter: Deleter<'_>) -> Result<bool, Error>;
+
+	/// Lists public pastes that aren't hidden, newest first.
+	/// Password-protected pastes are listed, but never searched, or their content would leak through the results.
+	async fn browse(&self, search: Search<'_>) -> Result<Vec<Listed>, Error>;
+
+	/// Lists the newest public pastes that aren't hidden, optionally only in a language, or owned by a user.
+	async fn feed(
+		&self,
+		language: Option<&str>,
+		username: Option<&str>,
+		limit: i64,
+		excerpt_length: i64,
+	) -> Result<Vec<Entry>, Error>;
+
+	/// Lists a user's pastes, newest first.
+	/// Only the public ones that aren't hidden are listed, unless `all` is set.
+	async fn owned(&self, owner_id: i64, all: bool) -> Result<Vec<Owned>, Error>;
+
+	/// Works out the instance statistics, counting pastes for the last `days` days,
+	/// and the `top_languages` most popular languages.
+	async fn aggregates(&self, days: u32, top_languages: u32) -> Result<Aggregates, Error>;
+
+	/// Lists pastes for an admin, newest first.
+	/// `range` is the first and last stored IP to include, if the filter has a network.
+	async fn list(
+		&self,
+		filter: &Filter,
+		range: Option<(&[u8], &[u8])>,
+	) -> Result<Vec<pastes::Paste>, Error>;
+
+	/// Finds a paste for an admin, along with its content.
+	async fn inspect(&self, id: i64) -> Result<Option<(pastes::Paste, String)>, Error>;
+
+	/// Finds the stored IP of whoever uploaded a paste.
+	/// Returns [None] if there's no such paste, or its IP has been erased.
+	async fn uploader(&self, id: i64) -> Result<Option<Vec<u8>>, Error>;
+
+	/// Deletes every paste older than the given number of days, returning how many were deleted.
+	async fn purge(&self, days: u32) -> Result<u64, Error>;
+
+	/// Every paste, in order of ID.
+	fn export(&self) -> BoxStream<'_, Result<Dump, Error>>;
+
+	/// Stores exported pastes, all in one transaction, keeping their IDs.
+	/// Owners and API keys are matched by name, and dropped if there's no such user or key.
+	/// Pastes whose IDs are already taken are skipped.
+	/// Returns how many pastes were imported, and how many were skipped.
+	async fn import(&self, pastes: Vec<Dump>) -> Result<(u64, u64), Error>;
+
+	/// Reports a paste, returning whether it's now hidden, or [None] if there's no such paste.
+	/// The reporter's IP should already be in its stored form.
+	/// Pastes are hidden once `hide_after` different IPs have reported them, unless it's 0.
+	async fn report(
+		&self,
+		paste_id: i64,
+		reason: &str,
+		ip: &[u8],
+		hide_after: u32,
+	) -> Result<Option<bool>, Error>;
+
+	/// Lists every reported paste, most reported first.
+	async fn reported(&self) -> Result<Vec<Reported>, Error>;
+
+	/// Dismisses every report on a paste, and unhides it,
+	/// returning whether there were any reports.
+	async fn dismiss(&self, paste_id: i64) -> Result<bool, Error>;
+
+	/// Erases the IPs of pastes and reports older than the given number of days,
+	/// returning how many pastes were affected.
+	async fn scrub_ips(&self, days: u32) -> Result<u64, Error>;
+
+	/// Creates a user, with an already hashed password, returning whether the username was free.
+	/// Usernames are compared case-insensitively.
+	async fn create_user(&self, username: &str, password: &str) -> Result<bool, Error>;
+
+	/// Deletes a user and their sessions, returning whether they existed.
+	/// Their pastes are kept, but no longer have an owner.
+	async fn delete_user(&self, username: &str) -> Result<bool, Error>;
+
+	/// Lists every username.
+	async fn users(&self) -> Result<Vec<String>, Error>;
+
+	/// Finds a user by name, along with their password hash.
+	async fn find_user(&self, username: &str) -> Result<Option<(User, String)>, Error>;
+
+	/// Starts a session for a user, lasting the given number of days.
+	/// Sessions that have expired are cleaned up while we're at it.
+	async fn create_session(&self, token_hash: &[u8], user_id: i64, days: u32)
+		-> Result<(), Error>;
+
+	/// Finds the user that a session belongs to, if it's still valid.
+	async fn session_user(&self, token_hash: &[u8]) -> Result<Option<User>, Error>;
+
+	/// Ends a session.
+	async fn delete_session(&self, token_hash: &[u8]) -> Result<(), Error>;
+
+	/// Creates an API key, with its scopes separated by commas.
+	async fn create_key(
+		&self,
+		name: &str,
+		key_hash: &[u8],
+		scopes: &str,
+		expires_in_days: Option<u32>,
+	) -> Result<(), Error>;
+
+	/// Finds an API key that hasn't expired, and marks it as used.
+	/// Returns its ID, and its scopes separated by commas.
+	async fn use_key(&self, key_hash: &[u8]) -> Result<Option<(i64, String)>, Error>;
+
+	/// Lists every API key.
+	async fn keys(&self) -> Result<Vec<ApiKey>, Error>;
+
+	/// Revokes the API key with the given name, returning whether it existed.
+	/// Pastes uploaded with it are kept, but no longer belong to any key.
+	async fn revoke_key(&self, name: &str) -> Result<bool, Error>;
+
+	/// Finds an active ban covering the given IP address, as bytes.
+	async fn find_ban(&self, ip: &[u8]) -> Result<Option<Ban>, Error>;
+
+	/// Bans a network, given in its normalized form, along with the first and last address it covers.
+	async fn add_ban(
+		&self,
+		network: &str,
+		range: (&[u8], &[u8]),
+		reason: Option<&str>,
+		expires_in_days: Option<u32>,
+	) -> Result<Ban, Error>;
+
+	/// Lists every ban that hasn't expired yet.
+	async fn bans(&self) -> Result<Vec<Ban>, Error>;
+
+	/// Lifts the ban with the given ID, returning whether it existed.
+	async fn lift_ban(&self, id: i64) -> Result<bool, Error>;
+
+	/// Makes sure the database is answering.
+	async fn ping(&self) -> Result<(), Error>;
+
+	/// Whether every migration we know of has been applied.
+	async fn migrated(&self) -> bool;
+
+	/// Copies the whole database to a new file while it's still in use.
+	async fn backup(&self, target: &Path) -> Result<(), Error>;
+
+	/// How many database connections are open, and how many of those are idle.
+	fn connections(&self) -> (u32, usize);
+
+	/// Closes every connection, waiting for them to finish up.
+	async fn close(&self);
+}
+
+/// Opens the configured database: PostgreSQL if there's a `database-url`, otherwise SQLite.
+pub async fn open(config: &Config) -> Result<Arc<dyn Storage>> {
+	Ok(match &config.database_url {
+		Some(url) => Arc::new(PostgresStorage::connect(url).await?),
+		None => Arc::new(SqliteStorage::open(&config.db_path).await?),
+	})
+}
+
+/// Escapes `%`, `_` and `\` in a string, so it can be used literally in a `LIKE` pattern.
+fn escape_like(s: &str) -> String {
+	s.replace('\\', "\\\\")
+		.replace('%', "\\%")
+		.replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::TryStreamExt;
+	use rand::Rng;
+
+	/// A paste, uploaded from the given IP.
+	fn paste<'a>(content: &'a str, ip: &'a [u8]) -> NewPaste<'a> {
+		NewPaste {
+			name: None,
+			ip,
+			syntax: None,
+			content,
+			password: None,
+			key_id: None,
+			owner_id: None,
+			visibility: "public",
+			size: content.len() as i64,
+		}
+	}
+
+	/// Puts a backend through everything sweetpaste asks of it.
+	/// Names and IPs are random, so this can run against a database that's already in use.
+	async fn exercise(storage: &dyn Storage) {
+		let tag = format!("t{:016x}", rand::thread_rng().gen::<u64>());
+		let ip = rand::thread_rng().gen::<[u8; 16]>();
+		assert!(storage.migrated().await);
+		storage.ping().await.unwrap();
+
+		// Quotas stop uploads once they're used up.
+		let quota = Quota {
+			first: ip.to_vec(),
+			last: ip.to_vec(),
+			hours: 1,
+			max_pastes: 2,
+			max_bytes: 0,
+		};
+		let content = format!("{} searchable", tag);
+		let (id, usage) = storage
+			.insert(paste(&content, &ip), Some(&quota))
+			.await
+			.unwrap();
+		assert_eq!(usage.map(|usage| usage.pastes), Some(1));
+		storage
+			.insert(paste("second", &ip), Some(&quota))
+			.await
+			.unwrap();
+		assert!(matches!(
+			storage.insert(paste("third", &ip), Some(&quota)).await,
+			Err(Error::QuotaExceeded)
+		));
+		let found = storage.find(id).await.unwrap().unwrap();
+		assert_eq!(found.content, content);
+		assert!(!found.hidden);
+		assert_eq!(storage.uploader(id).await.unwrap(), Some(ip.to_vec()));
+
+		// Browsing finds pastes by their content.
+		let listed = storage
+			.browse(Search {
+				words: Some(&tag),
+				name: None,
+				language: None,
+				limit: 10,
+				offset: 0,
+			})
+			.await
+			.unwrap();
+		assert_eq!(
+			listed.iter().map(|paste| paste.id).collect::<Vec<_>>(),
+			[id]
+		);
+		let (listed, _) = storage
+			.inspect(id)
+			.await
+			.unwrap()
+			.expect("pastes can be inspected");
+		assert_eq!(listed.size, content.len() as i64);
+
+		// Usernames are case-insensitive, and users can log in and own pastes.
+		let username = format!("User{}", tag);
+		assert!(storage.create_user(&username, "hash").await.unwrap());
+		assert!(!storage
+			.create_user(&username.to_lowercase(), "hash")
+			.await
+			.unwrap());
+		let (user, hash) = storage
+			.find_user(&username.to_uppercase())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(hash, "hash");
+		let token = tag.as_bytes();
+		storage.create_session(token, user.id, 1).await.unwrap();
+		assert_eq!(
+			storage
+				.session_user(token)
+				.await
+				.unwrap()
+				.map(|user| user.id),
+			Some(user.id)
+		);
+		storage.delete_session(token).await.unwrap();
+		assert!(storage.session_user(token).await.unwrap().is_none());
+		let (owned_id, _) = storage
+			.insert(
+				NewPaste {
+					owner_id: Some(user.id),
+					..paste("owned paste", &ip)
+				},
+				None,
+			)
+			.await
+			.unwrap();
+		let owned = storage.owned(user.id, false).await.unwrap();
+		assert_eq!(
+			owned.iter().map(|paste| paste.id).collect::<Vec<_>>(),
+			[owned_id]
+		);
+		let feed = storage.feed(None, Some(&username), 10, 3).await.unwrap();
+		assert_eq!(feed.len(), 1);
+		assert_eq!(feed[0].excerpt, "owne");
+		assert_eq!(feed[0].username.as_deref(), Some(username.as_str()));
+
+		// Keys can be used, and revoked even after they've uploaded something.
+		let key_hash = format!("key {}", tag).into_bytes();
+		storage
+			.create_key(&tag, &key_hash, "upload", Some(1))
+			.await
+			.unwrap();
+		let (key_id, scopes) = storage.use_key(&key_hash).await.unwrap().unwrap();
+		assert_eq!(scopes, "upload");
+		let (keyed_id, _) = storage
+			.insert(
+				NewPaste {
+					key_id: Some(key_id),
+					..paste("keyed", &ip)
+				},
+				None,
+			)
+			.await
+			.unwrap();
+		assert!(storage
+			.keys()
+			.await
+			.unwrap()
+			.iter()
+			.any(|key| key.name == tag));
+		assert!(storage.revoke_key(&tag).await.unwrap());
+		assert!(storage.use_key(&key_hash).await.unwrap().is_none());
+		assert_eq!(storage.find(keyed_id).await.unwrap().unwrap().key_id, None);
+
+		// Enough reports hide a paste, until they're dismissed.
+		assert_eq!(
+			storage.report(id, "spam", &ip, 1).await.unwrap(),
+			Some(true)
+		);
+		assert!(storage.find(id).await.unwrap().unwrap().hidden);
+		assert!(!storage.cacheable(id).await.unwrap());
+		let reported = storage.reported().await.unwrap();
+		let reported = reported.iter().find(|paste| paste.id == id).unwrap();
+		assert_eq!((reported.reports, reported.reasons.as_str()), (1, "spam"));
+		assert!(storage.dismiss(id).await.unwrap());
+		assert!(!storage.find(id).await.unwrap().unwrap().hidden);
+		assert!(storage.cacheable(id).await.unwrap());
+		assert_eq!(storage.report(-1, "spam", &ip, 1).await.unwrap(), None);
+
+		// Bans cover their whole range, and nothing else.
+		let mut last = ip;
+		last[15] = 0xff;
+		let mut first = ip;
+		first[15] = 0;
+		let ban = storage
+			.add_ban(&tag, (&first, &last), Some("testing"), None)
+			.await
+			.unwrap();
+		assert_eq!(
+			storage.find_ban(&ip).await.unwrap().map(|ban| ban.id),
+			Some(ban.id)
+		);
+		let mut outside = ip;
+		outside[14] = outside[14].wrapping_add(1);
+		assert!(storage.find_ban(&outside).await.unwrap().is_none());
+		assert!(storage.bans().await.unwrap().iter().any(|b| b.id == ban.id));
+		assert!(storage.lift_ban(ban.id).await.unwrap());
+
+		// Pastes can only be deleted by whoever's allowed to.
+		let stranger = Deleter {
+			ip: Some(&outside),
+			any: false,
+			key_id: Some(key_id),
+			owner_id: Some(user.id),
+		};
+		assert!(!storage.delete(id, stranger).await.unwrap());
+		let nobody = Deleter {
+			ip: None,
+			any: false,
+			key_id: None,
+			owner_id: None,
+		};
+		assert!(!storage.delete(id, nobody).await.unwrap());
+		let uploader = Deleter {
+			ip: Some(&ip),
+			any: false,
+			key_id: None,
+			owner_id: None,
+		};
+		assert!(storage.delete(id, uploader).await.unwrap());
+		assert!(storage.find(id).await.unwrap().is_none());
+		assert!(!storage.cacheable(id).await.unwrap());
+		let (second_owned_id, _) = storage
+			.insert(
+				NewPaste {
+					owner_id: Some(user.id),
+					..paste("owned paste", &ip)
+				},
+				None,
+			)
+			.await
+			.unwrap();
+		let owner = Deleter {
+			ip: Some(&outside),
+			any: false,
+			key_id: None,
+			owner_id: Some(user.id),
+		};
+		assert!(storage.delete(second_owned_id, owner).await.unwrap());
+
+		// Deleting a user keeps their pastes.
+		assert!(storage.delete_user(&username).await.unwrap());
+		assert!(storage
+			.users()
+			.await
+			.unwrap()
+			.iter()
+			.all(|u| *u != username));
+		assert_eq!(
+			storage.find(owned_id).await.unwrap().unwrap().owner_id,
+			None
+		);
+
+		// Imports keep IDs and times, skip IDs that are taken, and carry on numbering after them.
+		let exported = storage.export().try_collect::<Vec<_>>().await.unwrap();
+		let imported_id = exported.iter().map(|paste| paste.id).max().unwrap() + 1;
+		let dump = |id| Dump {
+			id,
+			name: Some(tag.clone()),
+			syntax: Some(tag.clone()),
+			posted: "1900-01-01 00:00:00".to_string(),
+			content: "imported".to_string(),
+			password: None,
+			visibility: "unlisted".to_string(),
+			hidden: false,
+			ip: Some(ip.to_vec()),
+			owner: None,
+			key: None,
+		};
+		assert_eq!(
+			storage
+				.import(vec![dump(owned_id), dump(imported_id)])
+				.await
+				.unwrap(),
+			(1, 1)
+		);
+		let (next_id, _) = storage.insert(paste("next", &ip), None).await.unwrap();
+		assert!(next_id > imported_id);
+		let filter = Filter {
+			language: Some(tag.clone()),
+			limit: 10,
+			..Filter::default()
+		};
+		let listed = storage.list(&filter, Some((&ip, &ip))).await.unwrap();
+		assert_eq!(listed.len(), 1);
+		assert_eq!(listed[0].posted, "1900-01-01 00:00:00");
+
+		// Old IPs get erased, and old pastes purged.
+		assert!(storage.scrub_ips(36500).await.unwrap() >= 1);
+		assert_eq!(storage.uploader(imported_id).await.unwrap(), None);
+		assert!(storage.purge(36500).await.unwrap() >= 1);
+		assert!(storage.find(imported_id).await.unwrap().is_none());
+
+		let aggregates = storage.aggregates(1, 100).await.unwrap();
+		assert!(aggregates.total_pastes >= 4);
+		assert!(aggregates
+			.languages
+			.iter()
+			.all(|language| language.language != tag));
+	}
+
+	#[tokio::test]
+	async fn sqlite() {
+		let path = std::env::temp_dir().join(format!(
+			"sweetpaste-test-{:016x}.db",
+			rand::thread_rng().gen::<u64>()
+		));
+		let storage = SqliteStorage::open(&path).await.unwrap();
+		exercise(&storage).await;
+		// Backups are complete databases of their own.
+		let backup = path.with_extension("backup.db");
+		storage.backup(&backup).await.unwrap();
+		let restored = SqliteStorage::open(&backup).await.unwrap();
+		assert!(restored.migrated().await);
+		restored.close().await;
+		storage.close().await;
+		for database in [path, backup] {
+			for suffix in ["", "-wal", "-shm"] {
+				let mut file = database.clone().into_os_string();
+				file.push(suffix);
+				let _ = std::fs::remove_file(file);
+			}
+		}
+	}
+
+	/// Only runs with `SWEETPASTE_TEST_DATABASE_URL` set, like `postgres://postgres@localhost/sweetpaste_test`.
+	#[tokio::test]
+	async fn postgres() {
+		let url = match std::env::var("SWEETPASTE_TEST_DATABASE_URL") {
+			Ok(url) => url,
+			Err(_) => return,
+		};
+		let storage = PostgresStorage::connect(&url).await.unwrap();
+		exercise(&storage).await;
+		assert!(matches!(
+			storage.backup(Path::new("unused")).await,
+			Err(Error::Unsupported(_))
+		));
+		storage.close().await;
+	}
+}
@@ -0,0 +1,954 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use super::{
+	escape_like, Deleter, Dump, Entry, Listed, NewPaste, Owned, Paste, Search, Storage, Usage,
+};
+use crate::{
+	account::User,
+	auth::ApiKey,
+	bans::Ban,
+	error::Error,
+	pastes::{self, Filter},
+	quota::Quota,
+	reports::Reported,
+	stats::{Aggregates, Day, Language},
+};
+use async_trait::async_trait;
+use color_eyre::eyre::{Result, WrapErr};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use sqlx::{migrate::Migrator, PgPool};
+use std::{convert::TryFrom, path::Path};
+
+/// Our PostgreSQL migrations.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+/// Keeps everything in a PostgreSQL database, which several servers can share.
+pub struct PostgresStorage(PgPool);
+
+impl PostgresStorage {
+	/// Connects to the database, and brings it up to date.
+	pub async fn connect(url: &str) -> Result<Self> {
+		let pool = PgPool::connect(url)
+			.await
+			.wrap_err("failed to connect to postgres")?;
+		MIGRATOR
+			.run(&pool)
+			.await
+			.wrap_err("failed to run postgres migrations")?;
+		Ok(Self(pool))
+	}
+}
+
+/// Postgres has no unsigned integers, so counts of days and hours are bound as `INT`.
+fn int(n: u32) -> i32 {
+	i32::try_from(n).unwrap_or(i32::MAX)
+}
+
+/// A paste, as shown to an admin, along with its content.
+#[derive(sqlx::FromRow)]
+struct Inspected {
+	id: i64,
+	name: Option<String>,
+	syntax: Option<String>,
+	posted: String,
+	size: i64,
+	visibility: String,
+	hidden: bool,
+	locked: bool,
+	ip: Option<Vec<u8>>,
+	owner: Option<String>,
+	content: String,
+}
+
+// Our offline query data only covers SQLite, so these queries can't be checked at compile time.
+// Times are turned into text by `utc_text`, from the migrations, to match how SQLite keeps them.
+#[async_trait]
+impl Storage for PostgresStorage {
+	async fn insert(
+		&self,
+		paste: NewPaste<'_>,
+		quota: Option<&Quota>,
+	) -> Result<(i64, Option<Usage>), Error> {
+		let mut tx = self.0.begin().await?;
+		// Uploads counting towards the same quota wait for each other,
+		// so they can't all see room for themselves at once.
+		if let Some(quota) = quota {
+			sqlx::query(
+				"SELECT pg_advisory_xact_lock(hashtext(encode($1, 'hex') || encode($2, 'hex')))",
+			)
+			.bind(&quota.first)
+			.bind(&quota.last)
+			.execute(&mut tx)
+			.await?;
+		}
+		let id = sqlx::query_scalar(
+			r#"
+		INSERT INTO pastes
+			(name, ip, syntax, content, password, key_id, owner_id, visibility, size)
+		VALUES
+			($1, $2, $3, $4, $5, $6, $7, $8, $9)
+		RETURNING
+			id
+		"#,
+		)
+		.bind(paste.name)
+		.bind(paste.ip)
+		.bind(paste.syntax)
+		.bind(paste.content)
+		.bind(paste.password)
+		.bind(paste.key_id)
+		.bind(paste.owner_id)
+		.bind(paste.visibility)
+		.bind(paste.size)
+		.fetch_one(&mut tx)
+		.await?;
+		let usage = match quota {
+			Some(quota) => {
+				let usage: Usage = sqlx::query_as(
+					r#"
+		SELECT
+			COUNT(*) as pastes, COALESCE(SUM(size), 0)::BIGINT as bytes
+		FROM
+			pastes
+		WHERE
+			octet_length(ip) = octet_length($1) AND
+			ip BETWEEN $1 AND $2 AND
+			posted > now() - make_interval(hours => $3)
+		"#,
+				)
+				.bind(&quota.first)
+				.bind(&quota.last)
+				.bind(int(quota.hours))
+				.fetch_one(&mut tx)
+				.await?;
+				// Dropping the transaction rolls it back.
+				if !quota.allows(&usage) {
+					return Err(Error::QuotaExceeded);
+				}
+				Some(usage)
+			}
+			None => None,
+		};
+		tx.commit().await?;
+		Ok((id, usage))
+	}
+
+	async fn find(&self, id: i64) -> Result<Option<Paste>, Error> {
+		Ok(sqlx::query_as(
+			r#"
+		SELECT
+			name, syntax, content, EXTRACT(EPOCH FROM posted)::BIGINT as posted, password,
+			visibility, owner_id, key_id, hidden
+		FROM
+			pastes
+		WHERE
+			id = $1
+		"#,
+		)
+		.bind(id)
+		.fetch_optional(&self.0)
+		.await?)
+	}
+
+	async fn cacheable(&self, id: i64) -> Result<bool, Error> {
+		Ok(sqlx::query(
+			r#"
+		SELECT
+			id
+		FROM
+			pastes
+		WHERE
+			id = $1 AND visibility IN ('public', 'unlisted') AND NOT hidden AND password IS NULL
+		"#,
+		)
+		.bind(id)
+		.fetch_optional(&self.0)
+		.await?
+		.is_some())
+	}
+
+	async fn delete(&self, id: i64, deleter: Deleter<'_>) -> Result<bool, Error> {
+		Ok(sqlx::query(
+			r#"
+		DELETE FROM
+			pastes
+		WHERE
+			id = $1 AND
+			(ip = $2 OR $3 OR key_id = $4 OR owner_id = $5)
+		"#,
+		)
+		.bind(id)
+		.bind(deleter.ip)
+		.bind(deleter.any)
+		.bind(deleter.key_id)
+		.bind(deleter.owner_id)
+		.execute(&self.0)
+		.await?
+		.rows_affected()
+			> 0)
+	}
+
+	async fn browse(&self, search: Search<'_>) -> Result<Vec<Listed>, Error> {
+		// This has to match the `pastes_search` index exactly, for it to be used.
+		Ok(sqlx::query_as(
+			r#"
+		SELECT
+			id, name, syntax, utc_text(posted) as posted, size
+		FROM
+			pastes
+		WHERE
+			visibility = 'public' AND
+			NOT hidden AND
+			($1::TEXT IS NULL OR (
+				password IS NULL AND
+				to_tsvector('simple', left(coalesce(name, '') || ' ' || content, 262144))
+					@@ plainto_tsquery('simple', $1)
+			)) AND
+			($2::TEXT IS NULL OR name ILIKE '%' || $2 || '%' ESCAPE '\') AND
+			($3::TEXT IS NULL OR COALESCE(syntax, 'Plain Text') = $3)
+		ORDER BY
+			id DESC
+		LIMIT $4 OFFSET $5
+		"#,
+		)
+		.bind(search.words)
+		.bind(search.name.map(escape_like))
+		.bind(search.language)
+		.bind(search.limit)
+		.bind(search.offset)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn feed(
+		&self,
+		language: Option<&str>,
+		username: Option<&str>,
+		limit: i64,
+		excerpt_length: i64,
+	) -> Result<Vec<Entry>, Error> {
+		Ok(sqlx::query_as(
+			r#"
+		SELECT
+			pastes.id, pastes.name, pastes.syntax,
+			utc_text(pastes.posted) as posted,
+			pastes.password IS NOT NULL as locked,
+			left(pastes.content, ($4 + 1)::INT) as excerpt,
+			users.username
+		FROM
+			pastes
+		LEFT JOIN
+			users ON users.id = pastes.owner_id
+		WHERE
+			pastes.visibility = 'public' AND
+			NOT pastes.hidden AND
+			($1::TEXT IS NULL OR COALESCE(pastes.syntax, 'Plain Text') = $1) AND
+			($2::TEXT IS NULL OR lower(users.username) = lower($2))
+		ORDER BY
+			pastes.id DESC
+		LIMIT $3
+		"#,
+		)
+		.bind(language)
+		.bind(username)
+		.bind(limit)
+		.bind(excerpt_length)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn owned(&self, owner_id: i64, all: bool) -> Result<Vec<Owned>, Error> {
+		Ok(sqlx::query_as(
+			r#"
+		SELECT
+			id, name, syntax, utc_text(posted) as posted, visibility
+		FROM
+			pastes
+		WHERE
+			owner_id = $1 AND
+			($2 OR (visibility = 'public' AND NOT hidden))
+		ORDER BY
+			id DESC
+		"#,
+		)
+		.bind(owner_id)
+		.bind(all)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn aggregates(&self, days: u32, top_languages: u32) -> Result<Aggregates, Error> {
+		let totals: Usage = sqlx::query_as(
+			r#"
+		SELECT
+			COUNT(*) as pastes, COALESCE(SUM(size), 0)::BIGINT as bytes
+		FROM
+			pastes
+		"#,
+		)
+		.fetch_one(&self.0)
+		.await?;
+		let per_day: Vec<Day> = sqlx::query_as(
+			r#"
+		SELECT
+			to_char(posted AT TIME ZONE 'UTC', 'YYYY-MM-DD') as day, COUNT(*) as pastes
+		FROM
+			pastes
+		WHERE
+			posted AT TIME ZONE 'UTC' >= (now() AT TIME ZONE 'UTC')::DATE - $1
+		GROUP BY
+			day
+		ORDER BY
+			day
+		"#,
+		)
+		.bind(int(days))
+		.fetch_all(&self.0)
+		.await?;
+		let languages: Vec<Language> = sqlx::query_as(
+			r#"
+		SELECT
+			COALESCE(syntax, 'Plain Text') as language, COUNT(*) as pastes
+		FROM
+			pastes
+		GROUP BY
+			language
+		ORDER BY
+			COUNT(*) DESC
+		LIMIT $1
+		"#,
+		)
+		.bind(i64::from(top_languages))
+		.fetch_all(&self.0)
+		.await?;
+		Ok(Aggregates {
+			total_pastes: totals.pastes,
+			total_bytes: totals.bytes,
+			per_day,
+			languages,
+		})
+	}
+
+	async fn list(
+		&self,
+		filter: &Filter,
+		range: Option<(&[u8], &[u8])>,
+	) -> Result<Vec<pastes::Paste>, Error> {
+		let (first, last) = match range {
+			Some((first, last)) => (Some(first), Some(last)),
+			None => (None, None),
+		};
+		Ok(sqlx::query_as(
+			r#"
+		SELECT
+			pastes.id, pastes.name, pastes.syntax, utc_text(pastes.posted) as posted, pastes.size,
+			pastes.visibility, pastes.hidden, pastes.password IS NOT NULL as locked, pastes.ip,
+			users.username as owner
+		FROM
+			pastes
+		LEFT JOIN
+			users ON users.id = pastes.owner_id
+		WHERE
+			($1::TEXT IS NULL OR pastes.posted >= $1::TIMESTAMP AT TIME ZONE 'UTC') AND
+			($2::TEXT IS NULL OR pastes.posted < $2::TIMESTAMP AT TIME ZONE 'UTC') AND
+			($3::TEXT IS NULL OR pastes.syntax = $3) AND
+			($4::BYTEA IS NULL OR (
+				octet_length(pastes.ip) = octet_length($4) AND
+				pastes.ip BETWEEN $4 AND $5
+			))
+		ORDER BY
+			pastes.posted DESC, pastes.id DESC
+		LIMIT $6
+		"#,
+		)
+		.bind(filter.since.as_deref())
+		.bind(filter.until.as_deref())
+		.bind(filter.language.as_deref())
+		.bind(first)
+		.bind(last)
+		.bind(i64::from(filter.limit))
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn inspect(&self, id: i64) -> Result<Option<(pastes::Paste, String)>, Error> {
+		let paste: Option<Inspected> = sqlx::query_as(
+			r#"
+		SELECT
+			pastes.id, pastes.name, pastes.syntax, utc_text(pastes.posted) as posted, pastes.size,
+			pastes.visibility, pastes.hidden, pastes.password IS NOT NULL as locked, pastes.ip,
+			users.username as owner, pastes.content
+		FROM
+			pastes
+		LEFT JOIN
+			users ON users.id = pastes.owner_id
+		WHERE
+			pastes.id = $1
+		"#,
+		)
+		.bind(id)
+		.fetch_optional(&self.0)
+		.await?;
+		Ok(paste.map(|paste| {
+			(
+				pastes::Paste {
+					id: paste.id,
+					name: paste.name,
+					syntax: paste.syntax,
+					posted: paste.posted,
+					size: paste.size,
+					visibility: paste.visibility,
+					hidden: paste.hidden,
+					locked: paste.locked,
+					ip: paste.ip,
+					owner: paste.owner,
+				},
+				paste.content,
+			)
+		}))
+	}
+
+	async fn uploader(&self, id: i64) -> Result<Option<Vec<u8>>, Error> {
+		Ok(
+			sqlx::query_scalar::<_, Option<Vec<u8>>>("SELECT ip FROM pastes WHERE id = $1")
+				.bind(id)
+				.fetch_optional(&self.0)
+				.await?
+				.flatten(),
+		)
+	}
+
+	async fn purge(&self, days: u32) -> Result<u64, Error> {
+		Ok(sqlx::query(
+			r#"
+		DELETE FROM
+			pastes
+		WHERE
+			posted < now() - make_interval(days => $1)
+		"#,
+		)
+		.bind(int(days))
+		.execute(&self.0)
+		.await?
+		.rows_affected())
+	}
+
+	fn export(&self) -> BoxStream<'_, Result<Dump, Error>> {
+		sqlx::query_as(
+			r#"
+		SELECT
+			pastes.id, pastes.name, pastes.syntax, utc_text(pastes.posted) as posted, pastes.content,
+			pastes.password, pastes.visibility, pastes.hidden, pastes.ip,
+			users.username as owner, api_keys.name as key
+		FROM
+			pastes
+		LEFT JOIN
+			users ON users.id = pastes.owner_id
+		LEFT JOIN
+			api_keys ON api_keys.id = pastes.key_id
+		ORDER BY
+			pastes.id
+		"#,
+		)
+		.fetch(&self.0)
+		.map_err(Error::from)
+		.boxed()
+	}
+
+	async fn import(&self, pastes: Vec<Dump>) -> Result<(u64, u64), Error> {
+		let mut tx = self.0.begin().await?;
+		let (mut imported, mut skipped) = (0, 0);
+		for paste in pastes {
+			let size = paste.content.len() as i64;
+			let inserted = sqlx::query(
+				r#"
+		INSERT INTO pastes
+			(id, name, ip, syntax, posted, content, password, key_id, owner_id, visibility, size, hidden)
+		VALUES
+			(
+				$1, $2, $3, $4, $5::TIMESTAMP AT TIME ZONE 'UTC', $6, $7,
+				(SELECT id FROM api_keys WHERE name = $8),
+				(SELECT id FROM users WHERE lower(username) = lower($9)),
+				$10, $11, $12
+			)
+		ON CONFLICT (id) DO NOTHING
+		"#,
+			)
+			.bind(paste.id)
+			.bind(paste.name)
+			.bind(paste.ip)
+			.bind(paste.syntax)
+			.bind(paste.posted)
+			.bind(paste.content)
+			.bind(paste.password)
+			.bind(paste.key)
+			.bind(paste.owner)
+			.bind(paste.visibility)
+			.bind(size)
+			.bind(paste.hidden)
+			.execute(&mut tx)
+			.await?
+			.rows_affected();
+			if inserted > 0 {
+				imported += 1;
+			} else {
+				skipped += 1;
+			}
+		}
+		// Pastes were imported with their own IDs, so new pastes have to be numbered after them.
+		sqlx::query(
+			r#"
+		SELECT
+			setval(pg_get_serial_sequence('pastes', 'id'), MAX(id))
+		FROM
+			pastes
+		HAVING
+			MAX(id) IS NOT NULL
+		"#,
+		)
+		.execute(&mut tx)
+		.await?;
+		tx.commit().await?;
+		Ok((imported, skipped))
+	}
+
+	async fn report(
+		&self,
+		paste_id: i64,
+		reason: &str,
+		ip: &[u8],
+		hide_after: u32,
+	) -> Result<Option<bool>, Error> {
+		let mut tx = self.0.begin().await?;
+		// Make sure the paste actually exists first.
+		if sqlx::query("SELECT id FROM pastes WHERE id = $1")
+			.bind(paste_id)
+			.fetch_optional(&mut tx)
+			.await?
+			.is_none()
+		{
+			return Ok(None);
+		}
+		sqlx::query(
+			r#"
+		INSERT INTO reports
+			(paste_id, reason, ip)
+		VALUES
+			($1, $2, $3)
+		"#,
+		)
+		.bind(paste_id)
+		.bind(reason)
+		.bind(ip)
+		.execute(&mut tx)
+		.await?;
+		// Hide the paste, if enough different people have reported it.
+		let hidden = sqlx::query(
+			r#"
+		UPDATE
+			pastes
+		SET
+			hidden = TRUE
+		WHERE
+			id = $1 AND
+			$2 > 0 AND
+			(SELECT COUNT(DISTINCT ip) FROM reports WHERE paste_id = $1) >= $2
+		"#,
+		)
+		.bind(paste_id)
+		.bind(i64::from(hide_after))
+		.execute(&mut tx)
+		.await?
+		.rows_affected()
+			> 0;
+		tx.commit().await?;
+		Ok(Some(hidden))
+	}
+
+	async fn reported(&self) -> Result<Vec<Reported>, Error> {
+		Ok(sqlx::query_as(
+			r#"
+		SELECT
+			pastes.id, pastes.name, pastes.ip, pastes.hidden,
+			left(pastes.content, 500) as preview,
+			COUNT(reports.id) as reports,
+			string_agg(reports.reason, E'\n' ORDER BY reports.id) as reasons
+		FROM
+			pastes
+		INNER JOIN
+			reports ON reports.paste_id = pastes.id
+		GROUP BY
+			pastes.id
+		ORDER BY
+			COUNT(reports.id) DESC, MIN(reports.created)
+		"#,
+		)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn dismiss(&self, paste_id: i64) -> Result<bool, Error> {
+		let mut tx = self.0.begin().await?;
+		let dismissed = sqlx::query("DELETE FROM reports WHERE paste_id = $1")
+			.bind(paste_id)
+			.execute(&mut tx)
+			.await?
+			.rows_affected()
+			> 0;
+		sqlx::query("UPDATE pastes SET hidden = FALSE WHERE id = $1")
+			.bind(paste_id)
+			.execute(&mut tx)
+			.await?;
+		tx.commit().await?;
+		Ok(dismissed)
+	}
+
+	async fn scrub_ips(&self, days: u32) -> Result<u64, Error> {
+		let mut tx = self.0.begin().await?;
+		let scrubbed = sqlx::query(
+			r#"
+		UPDATE
+			pastes
+		SET
+			ip = NULL
+		WHERE
+			ip IS NOT NULL AND
+			posted < now() - make_interval(days => $1)
+		"#,
+		)
+		.bind(int(days))
+		.execute(&mut tx)
+		.await?
+		.rows_affected();
+		sqlx::query(
+			r#"
+		UPDATE
+			reports
+		SET
+			ip = NULL
+		WHERE
+			ip IS NOT NULL AND
+			created < now() - make_interval(days => $1)
+		"#,
+		)
+		.bind(int(days))
+		.execute(&mut tx)
+		.await?;
+		tx.commit().await?;
+		Ok(scrubbed)
+	}
+
+	async fn create_user(&self, username: &str, password: &str) -> Result<bool, Error> {
+		Ok(sqlx::query(
+			r#"
+		INSERT INTO users
+			(username, password)
+		VALUES
+			($1, $2)
+		ON CONFLICT DO NOTHING
+		"#,
+		)
+		.bind(username)
+		.bind(password)
+		.execute(&self.0)
+		.await?
+		.rows_affected()
+			> 0)
+	}
+
+	async fn delete_user(&self, username: &str) -> Result<bool, Error> {
+		let mut tx = self.0.begin().await?;
+		sqlx::query(
+			r#"
+		UPDATE
+			pastes
+		SET
+			owner_id = NULL
+		WHERE
+			owner_id = (SELECT id FROM users WHERE lower(username) = lower($1))
+		"#,
+		)
+		.bind(username)
+		.execute(&mut tx)
+		.await?;
+		// Sessions go along with the user.
+		let deleted = sqlx::query("DELETE FROM users WHERE lower(username) = lower($1)")
+			.bind(username)
+			.execute(&mut tx)
+			.await?
+			.rows_affected()
+			> 0;
+		tx.commit().await?;
+		Ok(deleted)
+	}
+
+	async fn users(&self) -> Result<Vec<String>, Error> {
+		Ok(
+			sqlx::query_scalar("SELECT username FROM users ORDER BY username")
+				.fetch_all(&self.0)
+				.await?,
+		)
+	}
+
+	async fn find_user(&self, username: &str) -> Result<Option<(User, String)>, Error> {
+		let user: Option<(i64, String, String)> = sqlx::query_as(
+			r#"
+		SELECT
+			id, username, password
+		FROM
+			users
+		WHERE
+			lower(username) = lower($1)
+		"#,
+		)
+		.bind(username)
+		.fetch_optional(&self.0)
+		.await?;
+		Ok(user.map(|(id, username, password)| (User { id, username }, password)))
+	}
+
+	async fn create_session(
+		&self,
+		token_hash: &[u8],
+		user_id: i64,
+		days: u32,
+	) -> Result<(), Error> {
+		// Clean up any sessions that have expired while we're at it.
+		sqlx::query("DELETE FROM sessions WHERE expires <= now()")
+			.execute(&self.0)
+			.await?;
+		sqlx::query(
+			r#"
+		INSERT INTO sessions
+			(token_hash, user_id, expires)
+		VALUES
+			($1, $2, now() + make_interval(days => $3))
+		"#,
+		)
+		.bind(token_hash)
+		.bind(user_id)
+		.bind(int(days))
+		.execute(&self.0)
+		.await?;
+		Ok(())
+	}
+
+	async fn session_user(&self, token_hash: &[u8]) -> Result<Option<User>, Error> {
+		Ok(sqlx::query_as(
+			r#"
+		SELECT
+			users.id, users.username
+		FROM
+			sessions
+		INNER JOIN
+			users ON users.id = sessions.user_id
+		WHERE
+			sessions.token_hash = $1 AND
+			sessions.expires > now()
+		"#,
+		)
+		.bind(token_hash)
+		.fetch_optional(&self.0)
+		.await?)
+	}
+
+	async fn delete_session(&self, token_hash: &[u8]) -> Result<(), Error> {
+		sqlx::query("DELETE FROM sessions WHERE token_hash = $1")
+			.bind(token_hash)
+			.execute(&self.0)
+			.await?;
+		Ok(())
+	}
+
+	async fn create_key(
+		&self,
+		name: &str,
+		key_hash: &[u8],
+		scopes: &str,
+		expires_in_days: Option<u32>,
+	) -> Result<(), Error> {
+		sqlx::query(
+			r#"
+		INSERT INTO api_keys
+			(name, key_hash, scopes, expires)
+		VALUES
+			($1, $2, $3, now() + make_interval(days => $4))
+		"#,
+		)
+		.bind(name)
+		.bind(key_hash)
+		.bind(scopes)
+		.bind(expires_in_days.map(int))
+		.execute(&self.0)
+		.await?;
+		Ok(())
+	}
+
+	async fn use_key(&self, key_hash: &[u8]) -> Result<Option<(i64, String)>, Error> {
+		Ok(sqlx::query_as(
+			r#"
+		UPDATE
+			api_keys
+		SET
+			last_used = now()
+		WHERE
+			key_hash = $1 AND
+			(expires IS NULL OR expires > now())
+		RETURNING
+			id, scopes
+		"#,
+		)
+		.bind(key_hash)
+		.fetch_optional(&self.0)
+		.await?)
+	}
+
+	async fn keys(&self) -> Result<Vec<ApiKey>, Error> {
+		Ok(sqlx::query_as(
+			r#"
+		SELECT
+			name, scopes, utc_text(created) as created,
+			utc_text(expires) as expires, utc_text(last_used) as last_used
+		FROM
+			api_keys
+		ORDER BY
+			id
+		"#,
+		)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn revoke_key(&self, name: &str) -> Result<bool, Error> {
+		let mut tx = self.0.begin().await?;
+		sqlx::query(
+			r#"
+		UPDATE
+			pastes
+		SET
+			key_id = NULL
+		WHERE
+			key_id = (SELECT id FROM api_keys WHERE name = $1)
+		"#,
+		)
+		.bind(name)
+		.execute(&mut tx)
+		.await?;
+		let revoked = sqlx::query("DELETE FROM api_keys WHERE name = $1")
+			.bind(name)
+			.execute(&mut tx)
+			.await?
+			.rows_affected()
+			> 0;
+		tx.commit().await?;
+		Ok(revoked)
+	}
+
+	async fn find_ban(&self, ip: &[u8]) -> Result<Option<Ban>, Error> {
+		Ok(sqlx::query_as(
+			r#"
+		SELECT
+			id, network, reason, utc_text(created) as created, utc_text(expires) as expires
+		FROM
+			bans
+		WHERE
+			octet_length(first) = octet_length($1) AND
+			$1 BETWEEN first AND last AND
+			(expires IS NULL OR expires > now())
+		LIMIT 1
+		"#,
+		)
+		.bind(ip)
+		.fetch_optional(&self.0)
+		.await?)
+	}
+
+	async fn add_ban(
+		&self,
+		network: &str,
+		(first, last): (&[u8], &[u8]),
+		reason: Option<&str>,
+		expires_in_days: Option<u32>,
+	) -> Result<Ban, Error> {
+		Ok(sqlx::query_as(
+			r#"
+		INSERT INTO bans
+			(network, first, last, reason, expires)
+		VALUES
+			($1, $2, $3, $4, now() + make_interval(days => $5))
+		RETURNING
+			id, network, reason, utc_text(created) as created, utc_text(expires) as expires
+		"#,
+		)
+		.bind(network)
+		.bind(first)
+		.bind(last)
+		.bind(reason)
+		.bind(expires_in_days.map(int))
+		.fetch_one(&self.0)
+		.await?)
+	}
+
+	async fn bans(&self) -> Result<Vec<Ban>, Error> {
+		Ok(sqlx::query_as(
+			r#"
+		SELECT
+			id, network, reason, utc_text(created) as created, utc_text(expires) as expires
+		FROM
+			bans
+		WHERE
+			expires IS NULL OR expires > now()
+		ORDER BY
+			id
+		"#,
+		)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn lift_ban(&self, id: i64) -> Result<bool, Error> {
+		Ok(sqlx::query("DELETE FROM bans WHERE id = $1")
+			.bind(id)
+			.execute(&self.0)
+			.await?
+			.rows_affected()
+			> 0)
+	}
+
+	async fn ping(&self) -> Result<(), Error> {
+		sqlx::query("SELECT 1").execute(&self.0).await?;
+		Ok(())
+	}
+
+	async fn migrated(&self) -> bool {
+		match sqlx::query_scalar::<_, i64>("SELECT version FROM _sqlx_migrations WHERE success")
+			.fetch_all(&self.0)
+			.await
+		{
+			Ok(applied) => MIGRATOR
+				.iter()
+				.all(|migration| applied.contains(&migration.version)),
+			Err(_) => false,
+		}
+	}
+
+	async fn backup(&self, _target: &Path) -> Result<(), Error> {
+		Err(Error::Unsupported(
+			"backups only cover SQLite, so use pg_dump to back up PostgreSQL",
+		))
+	}
+
+	fn connections(&self) -> (u32, usize) {
+		(self.0.size(), self.0.num_idle())
+	}
+
+	async fn close(&self) {
+		self.0.close().await;
+	}
+}
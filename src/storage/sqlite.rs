@@ -0,0 +1,1074 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use super::{
+	escape_like, Deleter, Dump, Entry, Listed, NewPaste, Owned, Paste, Search, Storage, Usage,
+};
+use crate::{
+	account::User,
+	auth::ApiKey,
+	bans::Ban,
+	error::Error,
+	pastes::{self, Filter},
+	quota::Quota,
+	reports::Reported,
+	stats::{Aggregates, Day, Language},
+};
+use async_trait::async_trait;
+use color_eyre::eyre::{Result, WrapErr};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use sqlx::{
+	migrate::Migrator,
+	sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqliteSynchronous},
+};
+use std::{convert::TryFrom, path::Path, time::Duration};
+
+/// Our SQLite migrations.
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Keeps everything in a single SQLite database.
+pub struct SqliteStorage(SqlitePool);
+
+impl SqliteStorage {
+	/// Opens the database, creating it if it doesn't exist yet, and brings it up to date.
+	pub async fn open(path: &Path) -> Result<Self> {
+		let pool = SqlitePool::connect_with(
+			SqliteConnectOptions::new()
+				.filename(path)
+				.create_if_missing(true)
+				// Write-ahead logging lets readers carry on while something's writing, backups included.
+				.journal_mode(SqliteJournalMode::Wal)
+				// With WAL, this is still safe from corruption, and only a power loss can lose the last commits.
+				.synchronous(SqliteSynchronous::Normal)
+				// Wait for other writers, rather than failing straight away.
+				.busy_timeout(Duration::from_secs(5))
+				.foreign_keys(true),
+		)
+		.await
+		.wrap_err("failed to open sqlite db")?;
+		// Run SQLite migrations
+		MIGRATOR
+			.run(&pool)
+			.await
+			.wrap_err("failed to run sqlite migrations")?;
+		Ok(Self(pool))
+	}
+}
+
+/// Turns a search into an FTS5 query, quoting every word,
+/// so that nothing typed into the search box is taken as FTS5 syntax.
+fn fts_query(search: &str) -> Option<String> {
+	let terms = search
+		.split_whitespace()
+		.map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+		.collect::<Vec<_>>();
+	if terms.is_empty() {
+		None
+	} else {
+		Some(terms.join(" "))
+	}
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+	async fn insert(
+		&self,
+		paste: NewPaste<'_>,
+		quota: Option<&Quota>,
+	) -> Result<(i64, Option<Usage>), Error> {
+		let (first, last, hours, max_pastes, max_bytes) = match quota {
+			Some(quota) => (
+				Some(quota.first.as_slice()),
+				Some(quota.last.as_slice()),
+				quota.hours,
+				i64::try_from(quota.max_pastes).unwrap_or(i64::MAX),
+				i64::try_from(quota.max_bytes).unwrap_or(i64::MAX),
+			),
+			None => (None, None, 0, 0, 0),
+		};
+		// A single statement is atomic, so nothing else can be uploaded
+		// between checking the quota and storing the paste.
+		let id = sqlx::query!(
+			r#"
+		INSERT INTO pastes
+			(name, ip, syntax, content, password, key_id, owner_id, visibility, size)
+		SELECT
+			$1, $2, $3, $4, $5, $6, $7, $8, $9
+		WHERE
+			$10 IS NULL OR (
+				SELECT
+					($13 = 0 OR COUNT(*) < $13) AND
+					($14 = 0 OR COALESCE(SUM(size), 0) + $9 <= $14)
+				FROM
+					pastes
+				WHERE
+					length(ip) = length($10) AND
+					ip BETWEEN $10 AND $11 AND
+					posted > datetime('now', '-' || $12 || ' hours')
+			)
+		RETURNING
+			id as "id: i64"
+		"#,
+			paste.name,
+			paste.ip,
+			paste.syntax,
+			paste.content,
+			paste.password,
+			paste.key_id,
+			paste.owner_id,
+			paste.visibility,
+			paste.size,
+			first,
+			last,
+			hours,
+			max_pastes,
+			max_bytes
+		)
+		.fetch_optional(&self.0)
+		.await?
+		.ok_or(Error::QuotaExceeded)?
+		.id;
+		// What's left of the quota is only for the uploader's information,
+		// so it doesn't matter if anything else was uploaded since.
+		let usage = match quota {
+			Some(quota) => Some(
+				sqlx::query_as!(
+					Usage,
+					r#"
+		SELECT
+			COUNT(*) as "pastes!: i64", COALESCE(SUM(size), 0) as "bytes!: i64"
+		FROM
+			pastes
+		WHERE
+			length(ip) = length($1) AND
+			ip BETWEEN $1 AND $2 AND
+			posted > datetime('now', '-' || $3 || ' hours')
+		"#,
+					quota.first,
+					quota.last,
+					quota.hours
+				)
+				.fetch_one(&self.0)
+				.await?,
+			),
+			None => None,
+		};
+		Ok((id, usage))
+	}
+
+	async fn find(&self, id: i64) -> Result<Option<Paste>, Error> {
+		Ok(sqlx::query_as!(
+			Paste,
+			r#"
+		SELECT
+			name, syntax, content, posted as "posted: i64", password,
+			visibility, owner_id, key_id, hidden as "hidden: bool"
+		FROM
+			pastes
+		WHERE
+			id = $1
+		"#,
+			id
+		)
+		.fetch_optional(&self.0)
+		.await?)
+	}
+
+	async fn cacheable(&self, id: i64) -> Result<bool, Error> {
+		Ok(sqlx::query!(
+			r#"
+		SELECT
+			id
+		FROM
+			pastes
+		WHERE
+			id = $1 AND visibility IN ('public', 'unlisted') AND NOT hidden AND password IS NULL
+		"#,
+			id
+		)
+		.fetch_optional(&self.0)
+		.await?
+		.is_some())
+	}
+
+	async fn delete(&self, id: i64, deleter: Deleter<'_>) -> Result<bool, Error> {
+		Ok(sqlx::query!(
+			r#"
+		DELETE FROM
+			pastes
+		WHERE
+			id = $1 AND
+			(ip = $2 OR $3 = 1 OR key_id = $4 OR owner_id = $5)
+		"#,
+			id,
+			deleter.ip,
+			deleter.any,
+			deleter.key_id,
+			deleter.owner_id
+		)
+		.execute(&self.0)
+		.await?
+		.rows_affected()
+			> 0)
+	}
+
+	async fn browse(&self, search: Search<'_>) -> Result<Vec<Listed>, Error> {
+		let words = search.words.and_then(fts_query);
+		let name = search.name.map(escape_like);
+		Ok(sqlx::query_as!(
+			Listed,
+			r#"
+		SELECT
+			id as "id!: i64", name, syntax, posted as "posted!: String", size as "size!: i64"
+		FROM
+			pastes
+		WHERE
+			visibility = 'public' AND
+			hidden = 0 AND
+			($1 IS NULL OR (
+				password IS NULL AND
+				id IN (SELECT rowid FROM pastes_fts WHERE pastes_fts MATCH $1)
+			)) AND
+			($2 IS NULL OR name LIKE '%' || $2 || '%' ESCAPE '\') AND
+			($3 IS NULL OR COALESCE(syntax, 'Plain Text') = $3)
+		ORDER BY
+			id DESC
+		LIMIT $4 OFFSET $5
+		"#,
+			words,
+			name,
+			search.language,
+			search.limit,
+			search.offset
+		)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn feed(
+		&self,
+		language: Option<&str>,
+		username: Option<&str>,
+		limit: i64,
+		excerpt_length: i64,
+	) -> Result<Vec<Entry>, Error> {
+		Ok(sqlx::query_as!(
+			Entry,
+			r#"
+		SELECT
+			pastes.id as "id!: i64", pastes.name, pastes.syntax,
+			pastes.posted as "posted!: String",
+			pastes.password IS NOT NULL as "locked!: bool",
+			substr(pastes.content, 1, $4 + 1) as "excerpt!: String",
+			(SELECT username FROM users WHERE users.id = pastes.owner_id) as "username?: String"
+		FROM
+			pastes
+		WHERE
+			pastes.visibility = 'public' AND
+			pastes.hidden = 0 AND
+			($1 IS NULL OR COALESCE(pastes.syntax, 'Plain Text') = $1) AND
+			($2 IS NULL OR pastes.owner_id = (SELECT id FROM users WHERE username = $2))
+		ORDER BY
+			pastes.id DESC
+		LIMIT $3
+		"#,
+			language,
+			username,
+			limit,
+			excerpt_length
+		)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn owned(&self, owner_id: i64, all: bool) -> Result<Vec<Owned>, Error> {
+		Ok(sqlx::query_as!(
+			Owned,
+			r#"
+		SELECT
+			id as "id!: i64", name, syntax, posted as "posted!: String", visibility
+		FROM
+			pastes
+		WHERE
+			owner_id = $1 AND
+			($2 = 1 OR (visibility = 'public' AND hidden = 0))
+		ORDER BY
+			id DESC
+		"#,
+			owner_id,
+			all
+		)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn aggregates(&self, days: u32, top_languages: u32) -> Result<Aggregates, Error> {
+		let totals = sqlx::query_as!(
+			Usage,
+			r#"
+		SELECT
+			COUNT(*) as "pastes!: i64", COALESCE(SUM(size), 0) as "bytes!: i64"
+		FROM
+			pastes
+		"#
+		)
+		.fetch_one(&self.0)
+		.await?;
+		let per_day = sqlx::query_as!(
+			Day,
+			r#"
+		SELECT
+			date(posted) as "day!: String", COUNT(*) as "pastes!: i64"
+		FROM
+			pastes
+		WHERE
+			posted >= date('now', '-' || $1 || ' days')
+		GROUP BY
+			date(posted)
+		ORDER BY
+			date(posted)
+		"#,
+			days
+		)
+		.fetch_all(&self.0)
+		.await?;
+		let languages = sqlx::query_as!(
+			Language,
+			r#"
+		SELECT
+			COALESCE(syntax, 'Plain Text') as "language!: String", COUNT(*) as "pastes!: i64"
+		FROM
+			pastes
+		GROUP BY
+			COALESCE(syntax, 'Plain Text')
+		ORDER BY
+			COUNT(*) DESC
+		LIMIT $1
+		"#,
+			top_languages
+		)
+		.fetch_all(&self.0)
+		.await?;
+		Ok(Aggregates {
+			total_pastes: totals.pastes,
+			total_bytes: totals.bytes,
+			per_day,
+			languages,
+		})
+	}
+
+	async fn list(
+		&self,
+		filter: &Filter,
+		range: Option<(&[u8], &[u8])>,
+	) -> Result<Vec<pastes::Paste>, Error> {
+		let (first, last) = match range {
+			Some((first, last)) => (Some(first), Some(last)),
+			None => (None, None),
+		};
+		Ok(sqlx::query_as!(
+			pastes::Paste,
+			r#"
+		SELECT
+			id as "id!: i64", name, syntax, posted as "posted!: String", size as "size!: i64",
+			visibility as "visibility!: String", hidden as "hidden!: bool",
+			password IS NOT NULL as "locked!: bool", ip,
+			(SELECT username FROM users WHERE users.id = pastes.owner_id) as "owner?: String"
+		FROM
+			pastes
+		WHERE
+			($1 IS NULL OR posted >= datetime($1)) AND
+			($2 IS NULL OR posted < datetime($2)) AND
+			($3 IS NULL OR syntax = $3) AND
+			($4 IS NULL OR (length(ip) = length($4) AND ip BETWEEN $4 AND $5))
+		ORDER BY
+			posted DESC, id DESC
+		LIMIT $6
+		"#,
+			filter.since,
+			filter.until,
+			filter.language,
+			first,
+			last,
+			filter.limit
+		)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn inspect(&self, id: i64) -> Result<Option<(pastes::Paste, String)>, Error> {
+		let paste = sqlx::query!(
+			r#"
+		SELECT
+			id as "id!: i64", name, syntax, posted as "posted!: String", size as "size!: i64",
+			visibility as "visibility!: String", hidden as "hidden!: bool",
+			password IS NOT NULL as "locked!: bool", ip,
+			(SELECT username FROM users WHERE users.id = pastes.owner_id) as "owner?: String",
+			content
+		FROM
+			pastes
+		WHERE
+			id = $1
+		"#,
+			id
+		)
+		.fetch_optional(&self.0)
+		.await?;
+		Ok(paste.map(|paste| {
+			(
+				pastes::Paste {
+					id: paste.id,
+					name: paste.name,
+					syntax: paste.syntax,
+					posted: paste.posted,
+					size: paste.size,
+					visibility: paste.visibility,
+					hidden: paste.hidden,
+					locked: paste.locked,
+					ip: paste.ip,
+					owner: paste.owner,
+				},
+				paste.content,
+			)
+		}))
+	}
+
+	async fn uploader(&self, id: i64) -> Result<Option<Vec<u8>>, Error> {
+		Ok(sqlx::query!(
+			r#"
+		SELECT
+			ip
+		FROM
+			pastes
+		WHERE
+			id = $1
+		"#,
+			id
+		)
+		.fetch_optional(&self.0)
+		.await?
+		.and_then(|paste| paste.ip))
+	}
+
+	async fn purge(&self, days: u32) -> Result<u64, Error> {
+		Ok(sqlx::query!(
+			r#"
+		DELETE FROM
+			pastes
+		WHERE
+			posted < datetime('now', '-' || $1 || ' days')
+		"#,
+			days
+		)
+		.execute(&self.0)
+		.await?
+		.rows_affected())
+	}
+
+	fn export(&self) -> BoxStream<'_, Result<Dump, Error>> {
+		sqlx::query_as!(
+			Dump,
+			r#"
+		SELECT
+			id as "id!: i64", name, syntax, posted as "posted!: String", content,
+			password, visibility as "visibility!: String", hidden as "hidden!: bool", ip,
+			(SELECT username FROM users WHERE users.id = pastes.owner_id) as "owner?: String",
+			(SELECT name FROM api_keys WHERE api_keys.id = pastes.key_id) as "key?: String"
+		FROM
+			pastes
+		ORDER BY
+			id
+		"#
+		)
+		.fetch(&self.0)
+		.map_err(Error::from)
+		.boxed()
+	}
+
+	async fn import(&self, pastes: Vec<Dump>) -> Result<(u64, u64), Error> {
+		let mut tx = self.0.begin().await?;
+		let (mut imported, mut skipped) = (0, 0);
+		for paste in pastes {
+			let size = paste.content.len() as i64;
+			let inserted = sqlx::query!(
+				r#"
+		INSERT INTO pastes
+			(id, name, ip, syntax, posted, content, password, key_id, owner_id, visibility, size, hidden)
+		VALUES
+			(
+				$1, $2, $3, $4, $5, $6, $7,
+				(SELECT id FROM api_keys WHERE name = $8),
+				(SELECT id FROM users WHERE username = $9),
+				$10, $11, $12
+			)
+		ON CONFLICT (id) DO NOTHING
+		"#,
+				paste.id,
+				paste.name,
+				paste.ip,
+				paste.syntax,
+				paste.posted,
+				paste.content,
+				paste.password,
+				paste.key,
+				paste.owner,
+				paste.visibility,
+				size,
+				paste.hidden
+			)
+			.execute(&mut tx)
+			.await?
+			.rows_affected();
+			if inserted > 0 {
+				imported += 1;
+			} else {
+				skipped += 1;
+			}
+		}
+		tx.commit().await?;
+		Ok((imported, skipped))
+	}
+
+	async fn report(
+		&self,
+		paste_id: i64,
+		reason: &str,
+		ip: &[u8],
+		hide_after: u32,
+	) -> Result<Option<bool>, Error> {
+		let mut tx = self.0.begin().await?;
+		// Make sure the paste actually exists first.
+		if sqlx::query!(
+			r#"
+		SELECT
+			id
+		FROM
+			pastes
+		WHERE
+			id = $1
+		"#,
+			paste_id
+		)
+		.fetch_optional(&mut tx)
+		.await?
+		.is_none()
+		{
+			return Ok(None);
+		}
+		sqlx::query!(
+			r#"
+		INSERT INTO reports
+			(paste_id, reason, ip)
+		VALUES
+			($1, $2, $3)
+		"#,
+			paste_id,
+			reason,
+			ip
+		)
+		.execute(&mut tx)
+		.await?;
+		// Hide the paste, if enough different people have reported it.
+		let hidden = sqlx::query!(
+			r#"
+		UPDATE
+			pastes
+		SET
+			hidden = 1
+		WHERE
+			id = $1 AND
+			$2 > 0 AND
+			(SELECT COUNT(DISTINCT ip) FROM reports WHERE paste_id = $1) >= $2
+		"#,
+			paste_id,
+			hide_after
+		)
+		.execute(&mut tx)
+		.await?
+		.rows_affected()
+			> 0;
+		tx.commit().await?;
+		Ok(Some(hidden))
+	}
+
+	async fn reported(&self) -> Result<Vec<Reported>, Error> {
+		Ok(sqlx::query_as!(
+			Reported,
+			r#"
+		SELECT
+			pastes.id as "id!: i64", pastes.name, pastes.ip, pastes.hidden as "hidden!: bool",
+			substr(pastes.content, 1, 500) as "preview!: String",
+			COUNT(reports.id) as "reports!: i64",
+			group_concat(reports.reason, char(10)) as "reasons!: String"
+		FROM
+			pastes
+		INNER JOIN
+			reports ON reports.paste_id = pastes.id
+		GROUP BY
+			pastes.id
+		ORDER BY
+			COUNT(reports.id) DESC, MIN(reports.created)
+		"#
+		)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn dismiss(&self, paste_id: i64) -> Result<bool, Error> {
+		let mut tx = self.0.begin().await?;
+		let dismissed = sqlx::query!(
+			r#"
+		DELETE FROM
+			reports
+		WHERE
+			paste_id = $1
+		"#,
+			paste_id
+		)
+		.execute(&mut tx)
+		.await?
+		.rows_affected()
+			> 0;
+		sqlx::query!(
+			r#"
+		UPDATE
+			pastes
+		SET
+			hidden = 0
+		WHERE
+			id = $1
+		"#,
+			paste_id
+		)
+		.execute(&mut tx)
+		.await?;
+		tx.commit().await?;
+		Ok(dismissed)
+	}
+
+	async fn scrub_ips(&self, days: u32) -> Result<u64, Error> {
+		let mut tx = self.0.begin().await?;
+		let scrubbed = sqlx::query!(
+			r#"
+		UPDATE
+			pastes
+		SET
+			ip = NULL
+		WHERE
+			ip IS NOT NULL AND
+			posted < datetime('now', '-' || $1 || ' days')
+		"#,
+			days
+		)
+		.execute(&mut tx)
+		.await?
+		.rows_affected();
+		sqlx::query!(
+			r#"
+		UPDATE
+			reports
+		SET
+			ip = NULL
+		WHERE
+			ip IS NOT NULL AND
+			created < datetime('now', '-' || $1 || ' days')
+		"#,
+			days
+		)
+		.execute(&mut tx)
+		.await?;
+		tx.commit().await?;
+		Ok(scrubbed)
+	}
+
+	async fn create_user(&self, username: &str, password: &str) -> Result<bool, Error> {
+		Ok(sqlx::query!(
+			r#"
+		INSERT INTO users
+			(username, password)
+		VALUES
+			($1, $2)
+		ON CONFLICT DO NOTHING
+		"#,
+			username,
+			password
+		)
+		.execute(&self.0)
+		.await?
+		.rows_affected()
+			> 0)
+	}
+
+	async fn delete_user(&self, username: &str) -> Result<bool, Error> {
+		let mut tx = self.0.begin().await?;
+		sqlx::query!(
+			r#"
+		UPDATE
+			pastes
+		SET
+			owner_id = NULL
+		WHERE
+			owner_id = (SELECT id FROM users WHERE username = $1)
+		"#,
+			username
+		)
+		.execute(&mut tx)
+		.await?;
+		sqlx::query!(
+			r#"
+		DELETE FROM
+			sessions
+		WHERE
+			user_id = (SELECT id FROM users WHERE username = $1)
+		"#,
+			username
+		)
+		.execute(&mut tx)
+		.await?;
+		let deleted = sqlx::query!(
+			r#"
+		DELETE FROM
+			users
+		WHERE
+			username = $1
+		"#,
+			username
+		)
+		.execute(&mut tx)
+		.await?
+		.rows_affected()
+			> 0;
+		tx.commit().await?;
+		Ok(deleted)
+	}
+
+	async fn users(&self) -> Result<Vec<String>, Error> {
+		Ok(sqlx::query!(
+			r#"
+		SELECT
+			username
+		FROM
+			users
+		ORDER BY
+			username
+		"#
+		)
+		.fetch_all(&self.0)
+		.await?
+		.into_iter()
+		.map(|user| user.username)
+		.collect())
+	}
+
+	async fn find_user(&self, username: &str) -> Result<Option<(User, String)>, Error> {
+		Ok(sqlx::query!(
+			r#"
+		SELECT
+			id as "id: i64", username, password
+		FROM
+			users
+		WHERE
+			username = $1
+		"#,
+			username
+		)
+		.fetch_optional(&self.0)
+		.await?
+		.map(|user| {
+			(
+				User {
+					id: user.id,
+					username: user.username,
+				},
+				user.password,
+			)
+		}))
+	}
+
+	async fn create_session(
+		&self,
+		token_hash: &[u8],
+		user_id: i64,
+		days: u32,
+	) -> Result<(), Error> {
+		// Clean up any sessions that have expired while we're at it.
+		sqlx::query!(
+			r#"
+		DELETE FROM
+			sessions
+		WHERE
+			expires <= CURRENT_TIMESTAMP
+		"#
+		)
+		.execute(&self.0)
+		.await?;
+		sqlx::query!(
+			r#"
+		INSERT INTO sessions
+			(token_hash, user_id, expires)
+		VALUES
+			($1, $2, datetime('now', '+' || $3 || ' days'))
+		"#,
+			token_hash,
+			user_id,
+			days
+		)
+		.execute(&self.0)
+		.await?;
+		Ok(())
+	}
+
+	async fn session_user(&self, token_hash: &[u8]) -> Result<Option<User>, Error> {
+		Ok(sqlx::query_as!(
+			User,
+			r#"
+		SELECT
+			users.id as "id: i64", users.username
+		FROM
+			sessions
+		INNER JOIN
+			users ON users.id = sessions.user_id
+		WHERE
+			sessions.token_hash = $1 AND
+			sessions.expires > CURRENT_TIMESTAMP
+		"#,
+			token_hash
+		)
+		.fetch_optional(&self.0)
+		.await?)
+	}
+
+	async fn delete_session(&self, token_hash: &[u8]) -> Result<(), Error> {
+		sqlx::query!(
+			r#"
+		DELETE FROM
+			sessions
+		WHERE
+			token_hash = $1
+		"#,
+			token_hash
+		)
+		.execute(&self.0)
+		.await?;
+		Ok(())
+	}
+
+	async fn create_key(
+		&self,
+		name: &str,
+		key_hash: &[u8],
+		scopes: &str,
+		expires_in_days: Option<u32>,
+	) -> Result<(), Error> {
+		sqlx::query!(
+			r#"
+		INSERT INTO api_keys
+			(name, key_hash, scopes, expires)
+		VALUES
+			($1, $2, $3, datetime('now', '+' || $4 || ' days'))
+		"#,
+			name,
+			key_hash,
+			scopes,
+			expires_in_days
+		)
+		.execute(&self.0)
+		.await?;
+		Ok(())
+	}
+
+	async fn use_key(&self, key_hash: &[u8]) -> Result<Option<(i64, String)>, Error> {
+		Ok(sqlx::query!(
+			r#"
+		UPDATE
+			api_keys
+		SET
+			last_used = CURRENT_TIMESTAMP
+		WHERE
+			key_hash = $1 AND
+			(expires IS NULL OR expires > CURRENT_TIMESTAMP)
+		RETURNING
+			id as "id!: i64", scopes as "scopes!: String"
+		"#,
+			key_hash
+		)
+		.fetch_optional(&self.0)
+		.await?
+		.map(|key| (key.id, key.scopes)))
+	}
+
+	async fn keys(&self) -> Result<Vec<ApiKey>, Error> {
+		Ok(sqlx::query_as!(
+			ApiKey,
+			r#"
+		SELECT
+			name, scopes, created as "created: String",
+			expires as "expires: String", last_used as "last_used: String"
+		FROM
+			api_keys
+		ORDER BY
+			id
+		"#
+		)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn revoke_key(&self, name: &str) -> Result<bool, Error> {
+		let mut tx = self.0.begin().await?;
+		sqlx::query!(
+			r#"
+		UPDATE
+			pastes
+		SET
+			key_id = NULL
+		WHERE
+			key_id = (SELECT id FROM api_keys WHERE name = $1)
+		"#,
+			name
+		)
+		.execute(&mut tx)
+		.await?;
+		let revoked = sqlx::query!(
+			r#"
+		DELETE FROM
+			api_keys
+		WHERE
+			name = $1
+		"#,
+			name
+		)
+		.execute(&mut tx)
+		.await?
+		.rows_affected()
+			> 0;
+		tx.commit().await?;
+		Ok(revoked)
+	}
+
+	async fn find_ban(&self, ip: &[u8]) -> Result<Option<Ban>, Error> {
+		Ok(sqlx::query_as!(
+			Ban,
+			r#"
+		SELECT
+			id as "id!: i64", network, reason,
+			created as "created: String", expires as "expires: String"
+		FROM
+			bans
+		WHERE
+			length(first) = length($1) AND
+			$1 BETWEEN first AND last AND
+			(expires IS NULL OR expires > CURRENT_TIMESTAMP)
+		LIMIT 1
+		"#,
+			ip
+		)
+		.fetch_optional(&self.0)
+		.await?)
+	}
+
+	async fn add_ban(
+		&self,
+		network: &str,
+		(first, last): (&[u8], &[u8]),
+		reason: Option<&str>,
+		expires_in_days: Option<u32>,
+	) -> Result<Ban, Error> {
+		Ok(sqlx::query_as!(
+			Ban,
+			r#"
+		INSERT INTO bans
+			(network, first, last, reason, expires)
+		VALUES
+			($1, $2, $3, $4, datetime('now', '+' || $5 || ' days'))
+		RETURNING
+			id as "id!: i64", network as "network!: String", reason as "reason: String",
+			created as "created!: String", expires as "expires: String"
+		"#,
+			network,
+			first,
+			last,
+			reason,
+			expires_in_days
+		)
+		.fetch_one(&self.0)
+		.await?)
+	}
+
+	async fn bans(&self) -> Result<Vec<Ban>, Error> {
+		Ok(sqlx::query_as!(
+			Ban,
+			r#"
+		SELECT
+			id as "id!: i64", network, reason,
+			created as "created: String", expires as "expires: String"
+		FROM
+			bans
+		WHERE
+			expires IS NULL OR expires > CURRENT_TIMESTAMP
+		ORDER BY
+			id
+		"#
+		)
+		.fetch_all(&self.0)
+		.await?)
+	}
+
+	async fn lift_ban(&self, id: i64) -> Result<bool, Error> {
+		Ok(sqlx::query!(
+			r#"
+		DELETE FROM
+			bans
+		WHERE
+			id = $1
+		"#,
+			id
+		)
+		.execute(&self.0)
+		.await?
+		.rows_affected()
+			> 0)
+	}
+
+	async fn ping(&self) -> Result<(), Error> {
+		sqlx::query!("SELECT 1 as one").fetch_one(&self.0).await?;
+		Ok(())
+	}
+
+	async fn migrated(&self) -> bool {
+		// This table belongs to sqlx, rather than us, so it can't be checked at compile time.
+		match sqlx::query_scalar::<_, i64>("SELECT version FROM _sqlx_migrations WHERE success = 1")
+			.fetch_all(&self.0)
+			.await
+		{
+			Ok(applied) => MIGRATOR
+				.iter()
+				.all(|migration| applied.contains(&migration.version)),
+			Err(_) => false,
+		}
+	}
+
+	async fn backup(&self, target: &Path) -> Result<(), Error> {
+		// `VACUUM INTO` copies everything from within a single read transaction,
+		// so the backup is consistent, and writes carry on meanwhile.
+		let target = target.to_string_lossy().into_owned();
+		sqlx::query!("VACUUM INTO $1", target)
+			.execute(&self.0)
+			.await?;
+		Ok(())
+	}
+
+	fn connections(&self) -> (u32, usize) {
+		(self.0.size(), self.0.num_idle())
+	}
+
+	async fn close(&self) {
+		// Closing every connection lets SQLite checkpoint the WAL.
+		self.0.close().await;
+	}
+}
@@ -0,0 +1,42 @@
+/*
+	Copyright (c) 2021 Lucy <lucy@absolucy.moe>
+
+	This Source Code Form is subject to the terms of the Mozilla Public
+	License, v. 2.0. If a copy of the MPL was not distributed with this
+	file, You can obtain one at http://mozilla.org/MPL/2.0/.
+*/
+
+use serde::Deserialize;
+
+/// Who can see a paste.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Visibility {
+	/// Anyone with the link, and shows up in listings.
+	Public,
+	/// Anyone with the link.
+	#[default]
+	Unlisted,
+	/// Only the uploader, and admins.
+	Private,
+}
+
+impl Visibility {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Public => "public",
+			Self::Unlisted => "unlisted",
+			Self::Private => "private",
+		}
+	}
+
+	/// Parses a visibility from the database.
+	/// Anything unexpected is treated as private, to be safe.
+	pub fn from_db(s: &str) -> Self {
+		match s {
+			"public" => Self::Public,
+			"unlisted" => Self::Unlisted,
+			_ => Self::Private,
+		}
+	}
+}